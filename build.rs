@@ -4,5 +4,7 @@ fn main() {
         .target("mos-c64")
         //.file("reu/src/fake_interrupt.c")
         .file("reu/src/fake_interrupt.S")
+        .file("reu/src/kernal_hooks.S")
+        .file("reu/src/snapshot.S")
         .compile("fake_interrupt");
 }
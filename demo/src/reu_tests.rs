@@ -1,7 +1,8 @@
 use core::ptr;
 use mos_hardware::c64::sid;
 use reu::ram_expansion_unit;
-use reu::REUArray;
+use reu::reu_pager;
+use reu::{REUArray, ReuPtr, ReuSafe};
 use ufmt_stdio::*; // stdio dla środowisk, które nie mają std
 
 pub fn alloc_test() {
@@ -17,7 +18,7 @@ pub fn alloc_test() {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Copy, ReuSafe)]
 struct GameUnit {
     number: u8,
     speed: u8,
@@ -45,9 +46,41 @@ pub fn test_reu_array() {
     array[50].x = 69;
     array[50].y = 11;
 
-    let sixty_nine = array.iter_mut().filter(|unit| unit.x == 69);
+    let sixty_nine = array.iter().filter(|unit| unit.x == 69);
 
     for u in sixty_nine {
         println!("{} Unit at x=69: ({},{})", u.number, u.x, u.y);
     }
 }
+
+#[derive(Clone, Copy, ReuSafe)]
+struct ListNode {
+    value: u16,
+    next: ReuPtr<ListNode>,
+}
+
+pub fn test_reu_list() {
+    reu_pager::init_pager(4);
+    let nodes = ram_expansion_unit::reu().alloc(1000 * core::mem::size_of::<ListNode>() as u32);
+    let first = ReuPtr::<ListNode>::from_chunk(&nodes);
+
+    // link 1000 nodes in reverse order
+    let mut head = ReuPtr::null();
+    for i in 0..1000 {
+        let node = first + i;
+        node.write(ListNode {
+            value: i as u16,
+            next: head,
+        });
+        head = node;
+    }
+
+    let mut sum: u32 = 0;
+    let mut node = head;
+    while !node.is_null() {
+        let n = node.read();
+        sum += n.value as u32;
+        node = n.next;
+    }
+    println!("sum of list: {}", sum);
+}
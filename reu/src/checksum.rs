@@ -0,0 +1,124 @@
+// Checksums that can be fed data in pieces, e.g. as it streams out of REU.
+// CRCs use 16-entry tables processing a nibble at a time: slower than the
+// usual 256-entry tables but they take 32 and 64 bytes instead of 512 and 1KB.
+
+/// CRC-16/CCITT-FALSE (polynomial 0x1021, initial value 0xFFFF)
+#[derive(Clone, Copy)]
+pub struct Crc16(u16);
+
+/// CRC-32 as used by zip and PNG (reflected polynomial 0xEDB88320)
+#[derive(Clone, Copy)]
+pub struct Crc32(u32);
+
+/// Sum of all bytes modulo 65536, cheap but weak
+#[derive(Clone, Copy, Default)]
+pub struct Sum16(u16);
+
+const CRC16_TABLE: [u16; 16] = crc16_table();
+const CRC32_TABLE: [u32; 16] = crc32_table();
+
+impl Crc16 {
+    pub fn new() -> Self {
+        Crc16(0xFFFF)
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        let mut crc = self.0;
+        for &byte in bytes {
+            crc = (crc << 4) ^ CRC16_TABLE[((crc >> 12) as u8 ^ (byte >> 4)) as usize];
+            crc = (crc << 4) ^ CRC16_TABLE[((crc >> 12) as u8 ^ (byte & 0x0F)) as usize];
+        }
+        self.0 = crc;
+    }
+
+    pub fn finish(&self) -> u16 {
+        self.0
+    }
+}
+
+impl Default for Crc16 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Crc32(0xFFFF_FFFF)
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        let mut crc = self.0;
+        for &byte in bytes {
+            crc = (crc >> 4) ^ CRC32_TABLE[((crc as u8 ^ byte) & 0x0F) as usize];
+            crc = (crc >> 4) ^ CRC32_TABLE[((crc as u8 ^ (byte >> 4)) & 0x0F) as usize];
+        }
+        self.0 = crc;
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sum16 {
+    pub fn new() -> Self {
+        Sum16(0)
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = self.0.wrapping_add(byte as u16);
+        }
+    }
+
+    pub fn finish(&self) -> u16 {
+        self.0
+    }
+}
+
+const fn crc16_table() -> [u16; 16] {
+    let mut table = [0; 16];
+    let mut i = 0;
+    while i < 16 {
+        let mut crc = (i as u16) << 12;
+        let mut bit = 0;
+        while bit < 4 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+const fn crc32_table() -> [u32; 16] {
+    let mut table = [0; 16];
+    let mut i = 0;
+    while i < 16 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 4 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
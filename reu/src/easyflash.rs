@@ -0,0 +1,76 @@
+use crate::expansion_memory::ReadMemory;
+use core::ptr;
+
+/// Selects which 16KB bank shows in ROML and ROMH, write-only
+const BANK_REGISTER: usize = 0xDE00;
+/// Cartridge mode and LED, write-only
+const CONTROL_REGISTER: usize = 0xDE02;
+/// ROML at $8000 and ROMH at $A000
+const MODE_16K: u8 = 0x07;
+/// No cartridge ROM visible, only the RAM at $DF00
+const MODE_OFF: u8 = 0x04;
+/// ROML followed by ROMH, so a whole bank shows as one block
+const ROM_WINDOW: usize = 0x8000;
+const BANK_SIZE: u32 = 0x4000;
+const BANKS: u32 = 64;
+const CPU_PORT: usize = 0x01;
+/// LORAM and HIRAM set, both are needed to see ROML
+const CPU_PORT_ROMS: u8 = 0x03;
+
+/// EasyFlash cartridge as read-only memory
+///
+/// Addresses run through the 64 banks of 16KB, each being 8KB of ROML
+/// followed by 8KB of ROMH, the way the banks of a CRT image are laid out.
+/// A bank is read by switching it to $8000-$BFFF in 16K mode and copying
+/// from there, so the reading code and the RAM it reads into mustn't be in
+/// $8000-$BFFF. Afterwards the cartridge ROM is switched off again.
+///
+/// Arrays and string tables prepared offline can be read from flash:
+///
+/// ```ignore
+/// static FLASH: EasyFlash = EasyFlash::new();
+/// let levels = REUArray::<Level>::from_memory(&FLASH, 0x10000, 20, 2);
+/// ```
+pub struct EasyFlash;
+
+impl Default for EasyFlash {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EasyFlash {
+    pub const fn new() -> Self {
+        EasyFlash
+    }
+}
+
+impl ReadMemory for EasyFlash {
+    fn read(&self, c64_start: usize, address: u32, length: usize) {
+        crate::without_interrupts(|| unsafe {
+            let port = ptr::read_volatile(CPU_PORT as *const u8);
+            ptr::write_volatile(CPU_PORT as *mut u8, port | CPU_PORT_ROMS);
+            ptr::write_volatile(CONTROL_REGISTER as *mut u8, MODE_16K);
+
+            let mut done = 0;
+            while done < length as u32 {
+                let at = address + done;
+                let count = (length as u32 - done).min(BANK_SIZE - at % BANK_SIZE);
+                ptr::write_volatile(BANK_REGISTER as *mut u8, (at / BANK_SIZE) as u8);
+                ptr::copy_nonoverlapping(
+                    (ROM_WINDOW + (at % BANK_SIZE) as usize) as *const u8,
+                    (c64_start + done as usize) as *mut u8,
+                    count as usize,
+                );
+                done += count;
+            }
+
+            ptr::write_volatile(CONTROL_REGISTER as *mut u8, MODE_OFF);
+            ptr::write_volatile(CPU_PORT as *mut u8, port);
+        });
+    }
+
+    fn size(&self) -> u32 {
+        BANKS * BANK_SIZE
+    }
+}
@@ -0,0 +1,66 @@
+use crate::ram_expansion_unit;
+use core::ptr::addr_of_mut;
+
+static mut MEMORY: Option<&'static dyn ExpansionMemory> = None;
+
+/// Memory outside the C64 address space that can be read at 24-bit addresses
+///
+/// Besides every `ExpansionMemory` this is cartridge ROM like `EasyFlash`,
+/// which `REUArray::from_memory` and `REUStringTable::from_memory` read from.
+pub trait ReadMemory {
+    /// Copy `length` bytes at `address` into C64 RAM at `c64_start`
+    fn read(&self, c64_start: usize, address: u32, length: usize);
+
+    /// Size of the memory in bytes
+    fn size(&self) -> u32;
+}
+
+/// Memory outside the C64 address space, reached at 24-bit addresses
+///
+/// This is all `ReuChunk`, the allocator and the containers need, so they
+/// work the same on every device implementing it: the REU through
+/// `RamExpanstionUnit` or a GeoRAM through `GeoRam`.
+pub trait ExpansionMemory: ReadMemory {
+    /// Copy `length` bytes of C64 RAM at `c64_start` to `address`
+    fn write(&self, c64_start: usize, address: u32, length: usize);
+
+    /// Exchange `length` bytes of C64 RAM at `c64_start` with the ones at `address`
+    fn swap(&self, c64_start: usize, address: u32, length: usize);
+
+    /// Set `length` bytes at `address` to `value`
+    fn fill(&self, address: u32, length: u32, value: u8);
+
+    /// Copy `length` bytes from one address to another through `buffer` in RAM
+    ///
+    /// Copies front to back, so overlapping ranges are fine when moving down.
+    fn copy(&self, from: u32, to: u32, length: u32, buffer: &mut [u8]) {
+        assert!(!buffer.is_empty(), "empty copy buffer");
+        let mut done = 0;
+        while done < length {
+            let count = (length - done).min(buffer.len() as u32) as usize;
+            self.read(buffer.as_mut_ptr() as usize, from + done, count);
+            self.write(buffer.as_ptr() as usize, to + done, count);
+            done += count as u32;
+        }
+    }
+}
+
+/// Use `memory` for everything allocated from now on, instead of the REU
+///
+/// Pick the device at startup before the allocator is initialized, as
+/// chunks are always freed on the selected one.
+pub fn select_memory(memory: &'static dyn ExpansionMemory) {
+    unsafe {
+        *addr_of_mut!(MEMORY) = Some(memory);
+    }
+}
+
+/// Whether the REU is the selected expansion memory
+pub fn reu_selected() -> bool {
+    core::ptr::addr_eq(memory(), ram_expansion_unit::REU)
+}
+
+/// The selected expansion memory, the REU unless `select_memory` picked another
+pub fn memory() -> &'static dyn ExpansionMemory {
+    unsafe { (*addr_of_mut!(MEMORY)).unwrap_or_else(|| ram_expansion_unit::reu()) }
+}
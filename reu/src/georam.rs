@@ -0,0 +1,88 @@
+use crate::expansion_memory::{ExpansionMemory, ReadMemory};
+use core::ptr;
+
+/// 256-byte window into the selected GeoRAM page
+const WINDOW: usize = 0xDE00;
+/// Page within the 16KB block, write-only
+const PAGE_REGISTER: usize = 0xDFFE;
+/// 16KB block, write-only
+const BLOCK_REGISTER: usize = 0xDFFF;
+const PAGE_SIZE: u32 = 256;
+const PAGES_PER_BLOCK: u32 = 64;
+
+/// GeoRAM or NeoRAM cartridge
+///
+/// The memory is seen one 256-byte page at a time through `$DE00`, so every
+/// transfer is done by the CPU page by page and is much slower than REU DMA.
+/// I/O has to be banked in, which the allocator keeps while it works.
+///
+/// Select it for the allocator and containers with `select_memory`:
+///
+/// ```ignore
+/// static GEORAM: GeoRam = GeoRam::new(512 * 1024);
+/// expansion_memory::select_memory(&GEORAM);
+/// ```
+pub struct GeoRam {
+    size: u32,
+}
+
+impl GeoRam {
+    /// GeoRAM with `size` bytes, 512KB for the original cartridge and up to 4MB
+    pub const fn new(size: u32) -> Self {
+        GeoRam { size }
+    }
+
+    /// Map the page holding `address` into the window, returns the address's place in it
+    fn map(&self, address: u32) -> *mut u8 {
+        let page = address / PAGE_SIZE;
+        unsafe {
+            ptr::write_volatile(PAGE_REGISTER as *mut u8, (page % PAGES_PER_BLOCK) as u8);
+            ptr::write_volatile(BLOCK_REGISTER as *mut u8, (page / PAGES_PER_BLOCK) as u8);
+        }
+        (WINDOW + (address % PAGE_SIZE) as usize) as *mut u8
+    }
+
+    /// Call `f` for each piece of the range lying within one page, with the
+    /// piece mapped in the window, its offset from `address` and its length
+    fn for_pages(&self, address: u32, length: u32, mut f: impl FnMut(*mut u8, usize, usize)) {
+        let mut done = 0;
+        while done < length {
+            let at = address + done;
+            let count = (length - done).min(PAGE_SIZE - at % PAGE_SIZE);
+            f(self.map(at), done as usize, count as usize);
+            done += count;
+        }
+    }
+}
+
+impl ReadMemory for GeoRam {
+    fn read(&self, c64_start: usize, address: u32, length: usize) {
+        self.for_pages(address, length as u32, |window, offset, count| unsafe {
+            ptr::copy_nonoverlapping(window, (c64_start + offset) as *mut u8, count);
+        });
+    }
+
+    fn size(&self) -> u32 {
+        self.size
+    }
+}
+
+impl ExpansionMemory for GeoRam {
+    fn write(&self, c64_start: usize, address: u32, length: usize) {
+        self.for_pages(address, length as u32, |window, offset, count| unsafe {
+            ptr::copy_nonoverlapping((c64_start + offset) as *const u8, window, count);
+        });
+    }
+
+    fn swap(&self, c64_start: usize, address: u32, length: usize) {
+        self.for_pages(address, length as u32, |window, offset, count| unsafe {
+            ptr::swap_nonoverlapping(window, (c64_start + offset) as *mut u8, count);
+        });
+    }
+
+    fn fill(&self, address: u32, length: u32, value: u8) {
+        self.for_pages(address, length, |window, _, count| unsafe {
+            ptr::write_bytes(window, value, count);
+        });
+    }
+}
@@ -0,0 +1,417 @@
+use crate::reu_ram_disk::{FileHandle, RamDisk, MAX_NAME_LEN};
+use core::ptr::{self, addr_of_mut};
+use core::slice;
+
+// KERNAL vectors in the order of `__kernal_hook_vectors`
+const ILOAD: usize = 0x0330;
+const ISAVE: usize = 0x0332;
+const IOPEN: usize = 0x031A;
+const ICLOSE: usize = 0x031C;
+const ICHKIN: usize = 0x031E;
+const ICLRCH: usize = 0x0322;
+const IBASIN: usize = 0x0324;
+const IGETIN: usize = 0x032A;
+
+// memory configuration register
+const CPU_PORT: usize = 0x01;
+
+// KERNAL variables
+const STATUS: usize = 0x90;
+const LDTND: usize = 0x98; // number of open files
+const DFLTN: usize = 0x99; // current input device
+const EAL: usize = 0xAE; // end of loaded or saved data
+const FNLEN: usize = 0xB7;
+const LA: usize = 0xB8; // logical file number
+const SA: usize = 0xB9; // secondary address
+const FA: usize = 0xBA; // device number
+const FNADR: usize = 0xBB;
+const STAL: usize = 0xC1; // start of data to save
+const MEMUSS: usize = 0xC3; // load address passed to LOAD
+const LAT: usize = 0x0259; // logical file numbers of open files
+const FAT: usize = 0x0263; // their devices
+const SAT: usize = 0x026D; // and secondary addresses
+const MAX_OPEN_FILES: usize = 10;
+
+// STATUS bits
+const STATUS_VERIFY_ERROR: u8 = 0x10;
+const STATUS_EOF: u8 = 0x40;
+const STATUS_READ_TIMEOUT: u8 = 0x02;
+
+// KERNAL error numbers
+const TOO_MANY_FILES: u8 = 1;
+const FILE_OPEN: u8 = 2;
+const FILE_NOT_FOUND: u8 = 4;
+const MISSING_FILE_NAME: u8 = 8;
+const OUT_OF_MEMORY: u8 = 16; // BASIC's error number, for a file running past $FFFF
+
+// results of a handler, see kernal_hooks.S
+const HOOK_DONE: u8 = 0;
+const HOOK_ERROR: u8 = 1;
+const HOOK_PASS: u8 = 2;
+
+extern "C" {
+    fn __kernal_hook_load();
+    fn __kernal_hook_save();
+    fn __kernal_hook_open();
+    fn __kernal_hook_close();
+    fn __kernal_hook_chkin();
+    fn __kernal_hook_clrchn();
+    fn __kernal_hook_chrin();
+    fn __kernal_hook_getin();
+    static mut __kernal_hook_a: u8;
+    static mut __kernal_hook_x: u8;
+    static mut __kernal_hook_y: u8;
+    static mut __kernal_hook_vectors: [u16; 8];
+    static mut __kernal_hook_program_cpu_port: u8;
+}
+
+struct KernalHooks {
+    disk: RamDisk,
+    device: u8,
+    files: [Option<(u8, FileHandle)>; MAX_OPEN_FILES], // logical file number and file
+    input: usize,                                      // file selected by CHKIN
+}
+
+static mut HOOKS: Option<KernalHooks> = None;
+
+/// Serve KERNAL LOAD, SAVE, OPEN, CHKIN, CHRIN, GETIN, CLRCHN and CLOSE
+/// for device number `device` from `disk`
+///
+/// After this `LOAD"FILE",9` in BASIC (or any program calling the KERNAL)
+/// reads from the RAM disk and `SAVE"FILE",9` writes to it, replacing a file
+/// of the same name. Files can also be opened for reading and read with
+/// `INPUT#`/`GET#`. A trailing `*` matches any file starting with the name,
+/// drive prefixes like `0:` and `@0:` are ignored. Other devices go on to
+/// the original vectors.
+///
+/// The hooks call into this program, so it has to stay resident: it must
+/// not return to BASIC if BASIC programs are going to be loaded over it.
+/// The handlers save the compiler's imaginary registers in zero page and
+/// run on a stack of their own, so they can be called from BASIC, but any
+/// other zero page this program uses is shared with BASIC and the KERNAL.
+/// They run with the memory configuration set when this is called, and the
+/// caller's is restored when they return.
+pub fn install_kernal_hooks(disk: RamDisk, device: u8) {
+    uninstall_kernal_hooks();
+    unsafe {
+        *addr_of_mut!(HOOKS) = Some(KernalHooks {
+            disk,
+            device,
+            files: [None; MAX_OPEN_FILES],
+            input: 0,
+        });
+        __kernal_hook_program_cpu_port = peek(CPU_PORT);
+        crate::without_interrupts(|| {
+            for (i, (vector, hook)) in hook_vectors().iter().enumerate() {
+                (*addr_of_mut!(__kernal_hook_vectors))[i] = peek16(*vector);
+                poke16(*vector, *hook as usize as u16);
+            }
+        });
+    }
+}
+
+/// Restore the original KERNAL vectors, returns the disk that was served
+pub fn uninstall_kernal_hooks() -> Option<RamDisk> {
+    unsafe {
+        let hooks = (*addr_of_mut!(HOOKS)).take()?;
+        crate::without_interrupts(|| {
+            for (i, (vector, _)) in hook_vectors().iter().enumerate() {
+                poke16(*vector, (*addr_of_mut!(__kernal_hook_vectors))[i]);
+            }
+        });
+        Some(hooks.disk)
+    }
+}
+
+/// Run `f` with the disk served by the KERNAL hooks, if they are installed
+pub fn with_kernal_disk<R>(f: impl FnOnce(&mut RamDisk) -> R) -> Option<R> {
+    unsafe {
+        (*addr_of_mut!(HOOKS))
+            .as_mut()
+            .map(|hooks| f(&mut hooks.disk))
+    }
+}
+
+fn hook_vectors() -> [(usize, unsafe extern "C" fn()); 8] {
+    [
+        (ILOAD, __kernal_hook_load),
+        (ISAVE, __kernal_hook_save),
+        (IOPEN, __kernal_hook_open),
+        (ICLOSE, __kernal_hook_close),
+        (ICHKIN, __kernal_hook_chkin),
+        (ICLRCH, __kernal_hook_clrchn),
+        (IBASIN, __kernal_hook_chrin),
+        (IGETIN, __kernal_hook_getin),
+    ]
+}
+
+/// Hooks if the KERNAL was called for device `device`
+fn hooks_for(device: u8) -> Option<&'static mut KernalHooks> {
+    unsafe {
+        (*addr_of_mut!(HOOKS))
+            .as_mut()
+            .filter(|hooks| hooks.device == device)
+    }
+}
+
+/// LOAD, A is 0 to load or 1 to verify, X/Y the address used when SA is 0
+#[no_mangle]
+extern "C" fn __kernal_load_handler() -> u8 {
+    let Some(hooks) = hooks_for(peek(FA)) else {
+        return HOOK_PASS;
+    };
+    poke(STATUS, 0);
+    let Some(mut file) = find_file(&hooks.disk) else {
+        return error(FILE_NOT_FOUND);
+    };
+
+    let disk = &hooks.disk;
+    let mut header = [0u8; 2];
+    let _ = disk.read(&mut file, &mut header);
+    let start = if peek(SA) == 0 {
+        peek16(MEMUSS)
+    } else {
+        u16::from_le_bytes(header)
+    };
+    let file_len = disk.file_len(&file).unwrap_or(0) - file.position();
+    // only the part up to $FFFF is loaded, usize would wrap around to zero page
+    let room = 0x10000 - start as u32;
+    let len = file_len.min(room) as usize;
+
+    unsafe {
+        if __kernal_hook_a == 0 {
+            let ram = slice::from_raw_parts_mut(start as *mut u8, len);
+            let _ = disk.read(&mut file, ram);
+        } else {
+            let mut buffer = [0u8; 32];
+            let mut done = 0;
+            while done < len {
+                let Ok(count @ 1..) = disk.read(&mut file, &mut buffer) else {
+                    break;
+                };
+                let count = count.min(len - done);
+                let ram = slice::from_raw_parts((start as usize + done) as *const u8, count);
+                if ram != &buffer[..count] {
+                    poke(STATUS, STATUS_VERIFY_ERROR);
+                }
+                done += count;
+            }
+        }
+    }
+
+    if file_len > room {
+        return error(OUT_OF_MEMORY);
+    }
+    let end = start.wrapping_add(len as u16);
+    poke16(EAL, end);
+    set_registers(0, end as u8, (end >> 8) as u8);
+    HOOK_DONE
+}
+
+/// SAVE of STAL up to EAL
+#[no_mangle]
+extern "C" fn __kernal_save_handler() -> u8 {
+    let Some(hooks) = hooks_for(peek(FA)) else {
+        return HOOK_PASS;
+    };
+    poke(STATUS, 0);
+    let name = file_name();
+    if name.is_empty() {
+        return error(MISSING_FILE_NAME);
+    }
+
+    let disk = &mut hooks.disk;
+    let _ = disk.delete(name);
+    let Ok(mut file) = disk.create(name) else {
+        return error(MISSING_FILE_NAME);
+    };
+    let start = peek16(STAL);
+    let len = peek16(EAL).wrapping_sub(start);
+    let _ = disk.write(&mut file, &start.to_le_bytes());
+    let data = unsafe { slice::from_raw_parts(start as *const u8, len as usize) };
+    let _ = disk.write(&mut file, data);
+    HOOK_DONE
+}
+
+/// OPEN of LA for reading, entered into the KERNAL file tables so CHKIN finds it
+#[no_mangle]
+extern "C" fn __kernal_open_handler() -> u8 {
+    let Some(hooks) = hooks_for(peek(FA)) else {
+        return HOOK_PASS;
+    };
+    let open_files = peek(LDTND) as usize;
+    if open_files >= MAX_OPEN_FILES {
+        return error(TOO_MANY_FILES);
+    }
+    let la = peek(LA);
+    if (0..open_files).any(|i| peek(LAT + i) == la) {
+        return error(FILE_OPEN);
+    }
+    let Some(file) = find_file(&hooks.disk) else {
+        return error(FILE_NOT_FOUND);
+    };
+    let Some(slot) = hooks.files.iter().position(|f| f.is_none()) else {
+        return error(TOO_MANY_FILES);
+    };
+
+    hooks.files[slot] = Some((la, file));
+    poke(LAT + open_files, la);
+    poke(FAT + open_files, hooks.device);
+    poke(SAT + open_files, peek(SA) | 0x60);
+    poke(LDTND, open_files as u8 + 1);
+    poke(STATUS, 0);
+    HOOK_DONE
+}
+
+/// CLOSE of the logical file in A
+#[no_mangle]
+extern "C" fn __kernal_close_handler() -> u8 {
+    let la = unsafe { __kernal_hook_a };
+    let Some(hooks) = (unsafe { (*addr_of_mut!(HOOKS)).as_mut() }) else {
+        return HOOK_PASS;
+    };
+    let Some(slot) = hooks
+        .files
+        .iter()
+        .position(|f| matches!(f, Some((l, _)) if *l == la))
+    else {
+        return HOOK_PASS;
+    };
+    hooks.files[slot] = None;
+
+    // move the last table entry into the gap, like the KERNAL does
+    let open_files = peek(LDTND) as usize;
+    if let Some(i) = (0..open_files).find(|i| peek(LAT + i) == la) {
+        let last = open_files - 1;
+        poke(LAT + i, peek(LAT + last));
+        poke(FAT + i, peek(FAT + last));
+        poke(SAT + i, peek(SAT + last));
+        poke(LDTND, last as u8);
+    }
+    HOOK_DONE
+}
+
+/// CHKIN of the logical file in X
+#[no_mangle]
+extern "C" fn __kernal_chkin_handler() -> u8 {
+    let la = unsafe { __kernal_hook_x };
+    let Some(hooks) = (unsafe { (*addr_of_mut!(HOOKS)).as_mut() }) else {
+        return HOOK_PASS;
+    };
+    let Some(slot) = hooks
+        .files
+        .iter()
+        .position(|f| matches!(f, Some((l, _)) if *l == la))
+    else {
+        return HOOK_PASS;
+    };
+    hooks.input = slot;
+    poke(DFLTN, hooks.device);
+    HOOK_DONE
+}
+
+/// CLRCHN, forgets our input device and lets the KERNAL do the rest
+#[no_mangle]
+extern "C" fn __kernal_clrchn_handler() -> u8 {
+    if hooks_for(peek(DFLTN)).is_some() {
+        poke(DFLTN, 0);
+    }
+    HOOK_PASS
+}
+
+/// CHRIN, next byte of the input file in A
+#[no_mangle]
+extern "C" fn __kernal_chrin_handler() -> u8 {
+    let Some(hooks) = hooks_for(peek(DFLTN)) else {
+        return HOOK_PASS;
+    };
+    let Some((_, file)) = &mut hooks.files[hooks.input] else {
+        return HOOK_PASS;
+    };
+    let mut byte = [0u8];
+    let disk = &hooks.disk;
+    let status = match disk.read(file, &mut byte) {
+        Ok(1) if Ok(file.position()) == disk.file_len(file) => STATUS_EOF,
+        Ok(1) => 0,
+        _ => {
+            // read past the end
+            byte[0] = b'\r';
+            STATUS_EOF | STATUS_READ_TIMEOUT
+        }
+    };
+    poke(STATUS, peek(STATUS) | status);
+    unsafe {
+        __kernal_hook_a = byte[0];
+    }
+    HOOK_DONE
+}
+
+/// GETIN, for files the same as CHRIN
+#[no_mangle]
+extern "C" fn __kernal_getin_handler() -> u8 {
+    __kernal_chrin_handler()
+}
+
+/// Open the file named by SETNAM, a trailing `*` matches the start of a name
+fn find_file(disk: &RamDisk) -> Option<FileHandle> {
+    let name = file_name();
+    match name.split_last() {
+        Some((b'*', prefix)) => {
+            let mut found = [0u8; MAX_NAME_LEN];
+            let (matched, _) = disk.list().find(|(n, _)| n.starts_with(prefix))?;
+            found[..matched.len()].copy_from_slice(matched);
+            disk.open(&found[..matched.len()]).ok()
+        }
+        _ => disk.open(name).ok(),
+    }
+}
+
+/// File name set by SETNAM without drive prefix and `,type,mode` suffix
+fn file_name() -> &'static [u8] {
+    let mut name =
+        unsafe { slice::from_raw_parts(peek16(FNADR) as *const u8, peek(FNLEN) as usize) };
+    if let Some(rest) = name.strip_prefix(b"@") {
+        name = rest;
+    }
+    if let Some(colon) = name.iter().take(2).position(|c| *c == b':') {
+        name = &name[colon + 1..];
+    }
+    if let Some(comma) = name.iter().position(|c| *c == b',') {
+        name = &name[..comma];
+    }
+    name
+}
+
+/// Return with carry set and KERNAL error `number` in A
+fn error(number: u8) -> u8 {
+    unsafe {
+        __kernal_hook_a = number;
+    }
+    HOOK_ERROR
+}
+
+fn set_registers(a: u8, x: u8, y: u8) {
+    unsafe {
+        __kernal_hook_a = a;
+        __kernal_hook_x = x;
+        __kernal_hook_y = y;
+    }
+}
+
+fn peek(address: usize) -> u8 {
+    unsafe { ptr::read_volatile(address as *const u8) }
+}
+
+fn poke(address: usize, value: u8) {
+    unsafe { ptr::write_volatile(address as *mut u8, value) }
+}
+
+fn peek16(address: usize) -> u16 {
+    u16::from_le_bytes([peek(address), peek(address + 1)])
+}
+
+fn poke16(address: usize, value: u16) {
+    let [low, high] = value.to_le_bytes();
+    poke(address, low);
+    poke(address + 1, high);
+}
@@ -1,15 +1,70 @@
 #![no_std] // nie ładuj biblioteki std
 #![feature(panic_info_message)]
 
+// lets #[derive(ReuSafe)] refer to `::reu` from inside this crate
+extern crate self as reu;
+
+pub mod checksum;
+pub mod easyflash;
+pub mod expansion_memory;
+pub mod georam;
+pub mod kernal_hooks;
+pub mod lz;
+pub mod machine_snapshot;
 pub mod ram_expansion_unit;
 pub mod reu_allocator;
 pub mod reu_array;
+pub mod reu_bit_set;
+pub mod reu_box;
+pub mod reu_btree_map;
+pub mod reu_checksum;
+pub mod reu_compression;
+pub mod reu_diagnostics;
+pub mod reu_grid;
+pub mod reu_hash_map;
+pub mod reu_heap;
+pub mod reu_overlay;
+pub mod reu_pager;
+pub mod reu_persistent;
+pub mod reu_ptr;
+pub mod reu_ram_disk;
+pub mod reu_ring_buffer;
+pub mod reu_safe;
+pub mod reu_slab;
+pub mod reu_string_table;
 pub mod vectors;
 
+pub use easyflash::EasyFlash;
+pub use expansion_memory::{ExpansionMemory, ReadMemory};
+pub use georam::GeoRam;
 pub use ram_expansion_unit::RamExpanstionUnit;
-pub use reu_array::REUArray;
+pub use reu_array::{REUArray, WindowAlignment};
+pub use reu_bit_set::REUBitSet;
+pub use reu_box::REUBox;
+pub use reu_btree_map::REUBTreeMap;
+pub use reu_derive::ReuSafe;
+pub use reu_grid::{REUGrid, REUGridWindow};
+pub use reu_hash_map::REUHashMap;
+pub use reu_heap::{HeapHandle, ReuHeap};
+pub use reu_overlay::{OverlayId, OverlayManager};
+pub use reu_ptr::ReuPtr;
+pub use reu_ram_disk::{FileHandle, FsError, RamDisk};
+pub use reu_ring_buffer::REURingBuffer;
+pub use reu_safe::ReuSafe;
+pub use reu_slab::{ReuHandle, ReuSlab};
+pub use reu_string_table::{REUStringTable, StringId};
 
 extern "C" {
     fn __enable_mi();
     fn __disable_mi();
+    fn __save_disable_mi() -> u8;
+    fn __restore_mi(status: u8);
+}
+
+/// Run `f` with interrupts disabled, then restore the previous interrupt state
+pub(crate) fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let status = unsafe { __save_disable_mi() };
+    let result = f();
+    unsafe { __restore_mi(status) };
+    result
 }
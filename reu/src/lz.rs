@@ -0,0 +1,133 @@
+extern crate alloc;
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+// Compressed data is a sequence of one-byte tokens:
+//   0x00        end of data
+//   0x01..0x7F  that many literal bytes follow
+//   0x80..0xFF  copy (token & 0x7F) + MIN_MATCH bytes from `offset` bytes
+//               back in the output, offset follows as a little-endian u16
+// No bit fields and only byte counters, so the decoder is simple on 6502.
+
+const END: u8 = 0x00;
+const MATCH: u8 = 0x80;
+const MAX_LITERALS: usize = 0x7F;
+pub const MIN_MATCH: usize = 3;
+pub const MAX_MATCH: usize = MIN_MATCH + 0x7F;
+pub const MAX_OFFSET: usize = 0xFFFF;
+const HASH_SIZE: usize = 256;
+const NO_POSITION: usize = usize::MAX;
+
+/// Compress `input`, returns the compressed data
+///
+/// This module uses nothing but `alloc`, so host-side tools can include it
+/// with `#[path = "../reu/src/lz.rs"] mod lz;` to pack assets. The `reu`
+/// crate itself only builds for the C64.
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    compress_with(input, |bytes| output.extend_from_slice(bytes));
+    output
+}
+
+/// Compress `input`, handing the compressed data to `emit` piece by piece
+///
+/// Greedy matching with a 256-entry table of the last position of each
+/// 3-byte hash, small enough to run on the C64 itself.
+pub fn compress_with(input: &[u8], mut emit: impl FnMut(&[u8])) {
+    let mut last_seen = vec![NO_POSITION; HASH_SIZE];
+    let mut literals = 0;
+    let mut pos = 0;
+
+    while pos + MIN_MATCH <= input.len() {
+        let key = hash(input, pos);
+        let candidate = last_seen[key];
+        last_seen[key] = pos;
+
+        let len = if candidate != NO_POSITION && pos - candidate <= MAX_OFFSET {
+            match_len(input, candidate, pos)
+        } else {
+            0
+        };
+        if len < MIN_MATCH {
+            pos += 1;
+            continue;
+        }
+
+        emit_literals(&input[literals..pos], &mut emit);
+        let offset = pos - candidate;
+        emit(&[
+            MATCH | (len - MIN_MATCH) as u8,
+            offset as u8,
+            (offset >> 8) as u8,
+        ]);
+        for skipped in pos + 1..(pos + len).min(input.len() + 1 - MIN_MATCH) {
+            last_seen[hash(input, skipped)] = skipped;
+        }
+        pos += len;
+        literals = pos;
+    }
+
+    emit_literals(&input[literals..], &mut emit);
+    emit(&[END]);
+}
+
+/// Decompress `input` into `output`, returns the decompressed length
+pub fn decompress(input: &[u8], output: &mut [u8]) -> usize {
+    let mut bytes = input.iter();
+    decompress_with(|| *bytes.next().expect("bad compressed data"), output)
+}
+
+/// Decompress data read byte by byte from `next` into `output`, returns the decompressed length
+pub fn decompress_with(mut next: impl FnMut() -> u8, output: &mut [u8]) -> usize {
+    let mut pos = 0;
+    loop {
+        let token = next();
+        if token == END {
+            return pos;
+        }
+        if token & MATCH != 0 {
+            let len = (token & !MATCH) as usize + MIN_MATCH;
+            let low = next() as usize;
+            let offset = low | (next() as usize) << 8;
+            assert!(
+                offset != 0 && offset <= pos && len <= output.len() - pos,
+                "bad compressed data"
+            );
+            // byte by byte, a match may overlap what it's copying
+            for i in pos..pos + len {
+                output[i] = output[i - offset];
+            }
+            pos += len;
+        } else {
+            let len = token as usize;
+            assert!(len <= output.len() - pos, "bad compressed data");
+            for byte in output[pos..pos + len].iter_mut() {
+                *byte = next();
+            }
+            pos += len;
+        }
+    }
+}
+
+fn emit_literals(mut literals: &[u8], emit: &mut impl FnMut(&[u8])) {
+    while !literals.is_empty() {
+        let count = literals.len().min(MAX_LITERALS);
+        emit(&[count as u8]);
+        emit(&literals[..count]);
+        literals = &literals[count..];
+    }
+}
+
+fn hash(input: &[u8], pos: usize) -> usize {
+    (input[pos] ^ input[pos + 1].rotate_left(3) ^ input[pos + 2].rotate_left(6)) as usize
+}
+
+fn match_len(input: &[u8], from: usize, pos: usize) -> usize {
+    let max = (input.len() - pos).min(MAX_MATCH);
+    let mut len = 0;
+    while len < max && input[from + len] == input[pos + len] {
+        len += 1;
+    }
+    len
+}
@@ -0,0 +1,182 @@
+use crate::expansion_memory;
+use crate::ram_expansion_unit;
+use crate::reu_allocator::ReuChunk;
+use core::mem::size_of;
+use core::ptr;
+
+const RAM_SIZE: u32 = 0x10000;
+const COLOR_RAM: usize = 0xD800;
+const COLOR_RAM_SIZE: usize = 1024;
+const VIC: usize = 0xD000;
+const VIC_REGISTERS: usize = 47;
+const CIA1: usize = 0xDC00;
+const CIA2: usize = 0xDD00;
+const CIA_REGISTERS: usize = 16;
+
+// registers that are read but not written back
+const VIC_INTERRUPT_LATCH: usize = 0x19; // writing acknowledges interrupts
+const VIC_SPRITE_COLLISIONS: usize = 0x1E; // and $D01F, cleared by reading
+const CIA_ICR: usize = 0x0D; // reads and clears the interrupt flags
+const CIA_TOD_TENTHS: usize = 0x08;
+const CIA_TOD_HOURS: usize = 0x0B;
+const CIA_CRA: usize = 0x0E;
+const CIA_CRB: usize = 0x0F;
+const CIA_FORCE_LOAD: u8 = 0x10;
+const CIA_TOD_ALARM: u8 = 0x80;
+
+extern "C" {
+    fn __machine_snapshot() -> u8;
+    fn __machine_restore() -> !;
+}
+
+/// I/O chip registers as they were read at snapshot time
+#[derive(Clone, Copy, crate::ReuSafe)]
+struct IoState {
+    vic: [u8; VIC_REGISTERS],
+    cia1: [u8; CIA_REGISTERS],
+    cia2: [u8; CIA_REGISTERS],
+}
+
+const IO_STATE_OFFSET: u32 = RAM_SIZE + COLOR_RAM_SIZE as u32;
+
+/// A saved state of the whole machine in REU
+///
+/// `snapshot` copies all 64KB of RAM, including the RAM under the ROMs and
+/// I/O, along with color RAM, the CPU port and the VIC-II and CIA registers.
+/// `restore` puts it all back and continues where `snapshot` was called,
+/// like `longjmp`, so save states and "pause to menu" screens take a
+/// single call each.
+///
+/// Not everything can be saved, as some registers can't be read back:
+///
+/// - SID registers are write-only, the sound has to be set up again
+///   after a restore, e.g. by the next call of a music player
+/// - CIA interrupt masks and timer latches can't be read; the masks are
+///   left as they are and the timers are reloaded with the counts they had
+/// - the VIC-II raster interrupt line reads as the current raster line,
+///   so a raster interrupt has to be set up again
+/// - the REU registers themselves are in use during the copy
+///
+/// The copy is done by REU DMA, so the REU has to be the selected
+/// expansion memory.
+///
+/// The snapshot can only be restored by the same program, as it resumes in
+/// the code that took it. REU memory isn't part of it: chunks freed after
+/// the snapshot must not be used after a restore and chunks allocated after
+/// it are never freed.
+///
+/// To construct use `new`
+pub struct MachineSnapshot {
+    chunk: ReuChunk,
+    taken: bool,
+}
+
+impl Default for MachineSnapshot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MachineSnapshot {
+    /// Allocate REU memory for one snapshot, about 65KB
+    ///
+    /// The snapshot is taken with REU DMA, so the REU has to be the
+    /// selected expansion memory.
+    pub fn new() -> Self {
+        assert!(expansion_memory::reu_selected(), "snapshot needs the reu");
+        MachineSnapshot {
+            chunk: ram_expansion_unit::reu().alloc(IO_STATE_OFFSET + size_of::<IoState>() as u32),
+            taken: false,
+        }
+    }
+
+    /// Save the machine state
+    ///
+    /// Returns `false` right after saving and `true` when the machine
+    /// comes back here through `restore`.
+    pub fn snapshot(&mut self) -> bool {
+        let reu = ram_expansion_unit::reu();
+        let io = IoState {
+            vic: read_registers(VIC),
+            cia1: read_cia(CIA1),
+            cia2: read_cia(CIA2),
+        };
+        self.chunk.write(IO_STATE_OFFSET, &io);
+        self.chunk.push_at(reu, COLOR_RAM, RAM_SIZE, COLOR_RAM_SIZE);
+        // the copy sees `taken` set, so it can be restored again after a restore
+        self.taken = true;
+
+        reu.set_range(0, self.chunk.address, 0);
+        unsafe { __machine_snapshot() != 0 }
+    }
+
+    /// Bring the machine back to the state of the last `snapshot`, which
+    /// then returns `true`
+    pub fn restore(&self) -> ! {
+        assert!(self.taken, "no snapshot taken");
+        let reu = ram_expansion_unit::reu();
+        let io: IoState = self.chunk.read(IO_STATE_OFFSET);
+        self.chunk.pull_at(reu, COLOR_RAM, RAM_SIZE, COLOR_RAM_SIZE);
+        write_vic(&io.vic);
+        write_cia(CIA1, &io.cia1);
+        write_cia(CIA2, &io.cia2);
+
+        reu.set_range(0, self.chunk.address, 0);
+        unsafe { __machine_restore() }
+    }
+}
+
+fn read_registers<const N: usize>(base: usize) -> [u8; N] {
+    let mut registers = [0; N];
+    for (i, register) in registers.iter_mut().enumerate() {
+        *register = peek(base + i);
+    }
+    registers
+}
+
+fn read_cia(base: usize) -> [u8; CIA_REGISTERS] {
+    let mut registers = [0; CIA_REGISTERS];
+    for (i, register) in registers.iter_mut().enumerate() {
+        if i != CIA_ICR && !(CIA_TOD_TENTHS..=CIA_TOD_HOURS).contains(&i) {
+            *register = peek(base + i);
+        }
+    }
+    // reading hours latches the clock until tenths are read
+    for i in (CIA_TOD_TENTHS..=CIA_TOD_HOURS).rev() {
+        registers[i] = peek(base + i);
+    }
+    registers
+}
+
+fn write_vic(registers: &[u8; VIC_REGISTERS]) {
+    for (i, register) in registers.iter().enumerate() {
+        if i != VIC_INTERRUPT_LATCH && i != VIC_SPRITE_COLLISIONS && i != VIC_SPRITE_COLLISIONS + 1
+        {
+            poke(VIC + i, *register);
+        }
+    }
+}
+
+fn write_cia(base: usize, registers: &[u8; CIA_REGISTERS]) {
+    // ports, data directions and timer latches
+    for (i, register) in registers[..CIA_TOD_TENTHS].iter().enumerate() {
+        poke(base + i, *register);
+    }
+    // writing hours stops the clock until tenths are written, with
+    // CRB bit 7 clear so it's the clock and not the alarm
+    poke(base + CIA_CRB, registers[CIA_CRB] & !CIA_TOD_ALARM);
+    for i in (CIA_TOD_TENTHS..=CIA_TOD_HOURS).rev() {
+        poke(base + i, registers[i]);
+    }
+    // load the latches into the timers
+    poke(base + CIA_CRA, registers[CIA_CRA] | CIA_FORCE_LOAD);
+    poke(base + CIA_CRB, registers[CIA_CRB] | CIA_FORCE_LOAD);
+}
+
+fn peek(address: usize) -> u8 {
+    unsafe { ptr::read_volatile(address as *const u8) }
+}
+
+fn poke(address: usize, value: u8) {
+    unsafe { ptr::write_volatile(address as *mut u8, value) }
+}
@@ -1,11 +1,20 @@
+use crate::expansion_memory::{ExpansionMemory, ReadMemory};
 use bitflags::bitflags;
 use core::mem::size_of;
+use core::ptr::addr_of_mut;
 use static_assertions::const_assert;
 use ufmt_stdio::println;
 use volatile_register::{RO, RW}; // stdio dla środowisk, które nie mają std
 
 pub const REU: *const RamExpanstionUnit = (0xDF00) as _;
 static mut FILL_VALUE: u8 = 0xff;
+/// 24-bit addresses reach 256 banks of 64KB, smaller REUs repeat their memory within them
+const BANK_SIZE: u32 = 0x10000;
+const MAX_BANKS: usize = 256;
+/// REU size found by `size`, 0 until it's probed
+static mut SIZE: u32 = 0;
+/// Longest fill done with one DMA
+const MAX_FILL: u32 = 0x8000;
 
 /// REU base address
 pub fn reu() -> &'static RamExpanstionUnit {
@@ -104,6 +113,25 @@ impl RamExpanstionUnit {
         }
     }
 
+    /// Compare RAM with the REU range, `true` when they're equal
+    pub fn verify(&self) -> bool {
+        // reading status clears the fault flag of an earlier verify
+        let _ = self.status.read();
+        unsafe {
+            self.command.write(
+                Command::EXECUTE.bits() | Command::VERIFY.bits() | Command::NO_FF00_DECODE.bits(),
+            );
+        }
+        self.status.read() & Status::FAULT.bits() == 0
+    }
+
+    /// Copy `length` bytes from one REU address to another through `buffer` in RAM
+    ///
+    /// Copies front to back, so overlapping ranges are fine when moving down.
+    pub fn copy_reu(&self, from: u32, to: u32, length: u32, buffer: &mut [u8]) {
+        ExpansionMemory::copy(self, from, to, length, buffer);
+    }
+
     pub fn fill_reu(&self, reu_start: u32, length: usize, value: u8) {
         unsafe {
             FILL_VALUE = value;
@@ -136,3 +164,83 @@ impl RamExpanstionUnit {
         }
     }
 }
+
+/// Transfers with a length of 0 are skipped, for the REU that would be 64KB
+impl ReadMemory for RamExpanstionUnit {
+    fn read(&self, c64_start: usize, address: u32, length: usize) {
+        if length > 0 {
+            self.set_range(c64_start, address, length);
+            self.pull();
+        }
+    }
+
+    /// Probed on first use: each bank gets its number written to its first
+    /// byte from the top down, and as smaller REUs repeat their memory, each
+    /// bank reads back its own number only below the size. The bytes are
+    /// restored afterwards, so this can be done at any time.
+    fn size(&self) -> u32 {
+        unsafe {
+            if *addr_of_mut!(SIZE) == 0 {
+                *addr_of_mut!(SIZE) = crate::without_interrupts(|| self.probe_banks()) * BANK_SIZE;
+            }
+            *addr_of_mut!(SIZE)
+        }
+    }
+}
+
+impl RamExpanstionUnit {
+    /// Number of 64KB banks the REU has
+    ///
+    /// Addresses wrap at the end of memory, so after writing bank numbers
+    /// from the top down each bank reads back its own number only below the
+    /// size. The byte probed in each bank is restored afterwards.
+    pub(crate) fn probe_banks(&self) -> u32 {
+        let mut saved = [0u8; MAX_BANKS];
+        for (bank, byte) in saved.iter_mut().enumerate() {
+            self.read(byte as *mut u8 as usize, bank as u32 * BANK_SIZE, 1);
+        }
+        for bank in (0..MAX_BANKS).rev() {
+            self.write(
+                &(bank as u8) as *const u8 as usize,
+                bank as u32 * BANK_SIZE,
+                1,
+            );
+        }
+        let banks = (0..MAX_BANKS)
+            .find(|&bank| {
+                let mut byte = 0u8;
+                self.read(&mut byte as *mut u8 as usize, bank as u32 * BANK_SIZE, 1);
+                byte != bank as u8
+            })
+            .unwrap_or(MAX_BANKS);
+        for (bank, byte) in saved.iter().enumerate() {
+            self.write(byte as *const u8 as usize, bank as u32 * BANK_SIZE, 1);
+        }
+        banks as u32
+    }
+}
+
+impl ExpansionMemory for RamExpanstionUnit {
+    fn write(&self, c64_start: usize, address: u32, length: usize) {
+        if length > 0 {
+            self.set_range(c64_start, address, length);
+            self.push();
+        }
+    }
+
+    fn swap(&self, c64_start: usize, address: u32, length: usize) {
+        if length > 0 {
+            self.set_range(c64_start, address, length);
+            RamExpanstionUnit::swap(self);
+        }
+    }
+
+    fn fill(&self, address: u32, length: u32, value: u8) {
+        let mut done = 0;
+        while done < length {
+            let count = (length - done).min(MAX_FILL);
+            self.fill_reu(address + done, count as usize, value);
+            done += count;
+        }
+    }
+}
@@ -1,9 +1,13 @@
 extern crate alloc;
 
-use crate::ram_expansion_unit;
+use crate::expansion_memory::{self, ExpansionMemory};
 use crate::ram_expansion_unit::{Command, RamExpanstionUnit};
+use crate::reu_pager;
+use crate::reu_safe::ReuSafe;
 use crate::vectors::INTERRUPT_VECTORS;
+use core::mem::{self, size_of, MaybeUninit};
 use mos_hardware::{c64, cbm_kernal, vic2};
+use static_assertions::const_assert;
 use ufmt_stdio::println; // stdio dla środowisk, które nie mają std
 
 use mos_hardware::c64::{CpuPortFlags, CPU_PORT};
@@ -16,6 +20,12 @@ const ALLOCATION_UNIT_COUNT: usize = (AVAILABLE_REU / ALLOCATION_UNIT as u32) as
 const BOM_RAM_ADDRESS: usize = 0xE000;
 const BOM_REU_ADDRESS: u32 = 0x010000;
 const BOM_SIZE: usize = ALLOCATION_UNIT_COUNT / 8;
+/// Header of the persistent allocator, in the gap between the BOM and the pool
+pub(crate) const PERSISTENT_HEADER_ADDRESS: u32 = 0x011FE0;
+pub(crate) const PERSISTENT_HEADER_SIZE: usize =
+    (REU_POOL_START - PERSISTENT_HEADER_ADDRESS) as usize;
+
+const_assert!(BOM_REU_ADDRESS + BOM_SIZE as u32 <= PERSISTENT_HEADER_ADDRESS);
 
 static mut BOM: *mut Bom = BOM_RAM_ADDRESS as *mut Bom;
 
@@ -25,7 +35,8 @@ pub struct Bom {
 
 /// A chunk of REU memory with 24-bit addressing and length
 ///
-/// Allocate it using ram_expansion_unit::reu()::alloc(size)
+/// Allocate it using ram_expansion_unit::reu()::alloc(size), or
+/// expansion_memory::memory().alloc(size) for whichever device is selected
 /// use push and pull to move memory blocks between RAM and REU
 pub struct ReuChunk {
     pub address: u32,
@@ -34,22 +45,117 @@ pub struct ReuChunk {
 
 impl ReuChunk {
     /// Push C64 RAM contents into REU memory
-    pub fn push(&self, reu: &RamExpanstionUnit, c64_start: usize) {
-        reu.set_range(c64_start, self.address, self.len as usize);
-        reu.push();
+    pub fn push(&self, memory: &dyn ExpansionMemory, c64_start: usize) {
+        memory.write(c64_start, self.address, self.len as usize);
     }
 
     /// Pull data from REU memory into C64 RAM
-    pub fn pull(&self, reu: &RamExpanstionUnit, c64_start: usize) {
-        reu.set_range(c64_start, self.address, self.len as usize);
-        reu.pull();
+    pub fn pull(&self, memory: &dyn ExpansionMemory, c64_start: usize) {
+        memory.read(c64_start, self.address, self.len as usize);
+    }
+
+    /// Push `length` bytes of C64 RAM into the chunk, starting `offset` bytes in
+    pub fn push_at(
+        &self,
+        memory: &dyn ExpansionMemory,
+        c64_start: usize,
+        offset: u32,
+        length: usize,
+    ) {
+        if self.check_range(offset, length) {
+            memory.write(c64_start, self.address + offset, length);
+        }
+    }
+
+    /// Pull `length` bytes starting `offset` bytes into the chunk into C64 RAM
+    pub fn pull_at(
+        &self,
+        memory: &dyn ExpansionMemory,
+        c64_start: usize,
+        offset: u32,
+        length: usize,
+    ) {
+        if self.check_range(offset, length) {
+            memory.read(c64_start, self.address + offset, length);
+        }
+    }
+
+    /// Read a `T` stored `offset` bytes into the chunk
+    pub fn read<T: ReuSafe>(&self, offset: u32) -> T {
+        let mut value = MaybeUninit::<T>::uninit();
+        self.pull_at(
+            expansion_memory::memory(),
+            value.as_mut_ptr() as usize,
+            offset,
+            size_of::<T>(),
+        );
+        unsafe { value.assume_init() }
+    }
+
+    /// Store `value` at `offset` bytes into the chunk
+    pub fn write<T: ReuSafe>(&self, offset: u32, value: &T) {
+        self.push_at(
+            expansion_memory::memory(),
+            value as *const T as usize,
+            offset,
+            size_of::<T>(),
+        );
+    }
+
+    /// Size of the chunk in bytes
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Keep the memory allocated until the program ends, it's never freed
+    ///
+    /// A restart with `open_allocator` reclaims it, use `persist` of
+    /// `dyn ExpansionMemory` for memory that has to outlive the program.
+    pub fn leak(self) {
+        let _ = self.into_raw();
+    }
+
+    /// Give up ownership, returns address and length to rebuild the chunk with `from_raw`
+    pub fn into_raw(self) -> (u32, u32) {
+        let raw = (self.address, self.len);
+        mem::forget(self);
+        raw
+    }
+
+    /// Take ownership of memory given up with `into_raw` or `leak`
+    ///
+    /// # Safety
+    ///
+    /// `address` and `len` must come from `into_raw` of a chunk whose memory
+    /// wasn't reclaimed since, e.g. by `init_allocator` or by `open_allocator`
+    /// for a chunk that wasn't persisted, and no other chunk may own it, as
+    /// it's freed when the new chunk is dropped.
+    pub unsafe fn from_raw(address: u32, len: u32) -> ReuChunk {
+        ReuChunk { address, len }
+    }
+
+    /// Check the range lies within the chunk, `false` when there's nothing
+    /// to transfer (REU would treat length 0 as 64KB)
+    fn check_range(&self, offset: u32, length: usize) -> bool {
+        assert!(
+            offset + length as u32 <= self.len,
+            "reu range {}+{}/{}",
+            offset,
+            length,
+            self.len
+        );
+        length > 0
     }
 }
 
 impl Drop for ReuChunk {
     fn drop(&mut self) {
         unsafe {
-            ram_expansion_unit::reu().dealloc(&self);
+            expansion_memory::memory().dealloc(self);
         }
     }
 }
@@ -91,12 +197,26 @@ fn as_blocks(size: u32) -> usize {
     ((size + ALLOCATION_UNIT as u32 - 1) / ALLOCATION_UNIT as u32) as usize
 }
 
+/// The allocator works on the selected expansion memory, see `expansion_memory::select_memory`
 impl RamExpanstionUnit {
     pub fn init_allocator(&self) {
-        self.fill_reu(BOM_REU_ADDRESS, BOM_SIZE, 0);
+        expansion_memory::memory().init_allocator();
     }
 
     /// Allocate a chunk of REU memory with given size
+    pub fn alloc(&self, size: u32) -> ReuChunk {
+        expansion_memory::memory().alloc(size)
+    }
+}
+
+impl dyn ExpansionMemory {
+    pub fn init_allocator(&self) {
+        self.fill(BOM_REU_ADDRESS, BOM_SIZE as u32, 0);
+        // whatever was kept by `open_allocator` is gone now
+        self.fill(PERSISTENT_HEADER_ADDRESS, PERSISTENT_HEADER_SIZE as u32, 0);
+    }
+
+    /// Allocate a chunk of expansion memory with given size
     pub fn alloc(&self, size: u32) -> ReuChunk {
         if size == 0 {
             panic!("reu 0 alloc");
@@ -107,8 +227,11 @@ impl RamExpanstionUnit {
         let blocks_needed = as_blocks(size);
         let mut free_blocks = 0;
         let mut start_block = 0;
+        // memories smaller than 16MB use only the start of the BOM
+        let units = ALLOCATION_UNIT_COUNT
+            .min((self.size().saturating_sub(REU_POOL_START) / ALLOCATION_UNIT as u32) as usize);
 
-        'outer: for i in 0..(ALLOCATION_UNIT_COUNT) {
+        'outer: for i in 0..units {
             unsafe {
                 if (*BOM).is_free(i) {
                     if free_blocks == 0 {
@@ -145,6 +268,8 @@ impl RamExpanstionUnit {
     unsafe fn dealloc(&self, ptr: &ReuChunk) {
         let offset = ((ptr.address - REU_POOL_START) / ALLOCATION_UNIT as u32) as usize;
         let blocks_needed = as_blocks(ptr.len);
+        // a dirty frame written back later would land in the next owner's data
+        reu_pager::discard_pages(ptr.address, ptr.len);
 
         self.swap_bom_in();
         for i in offset..offset + blocks_needed {
@@ -153,19 +278,31 @@ impl RamExpanstionUnit {
         self.swap_bom_out();
     }
 
+    /// Start over with only the `live` chunks, given as address and length, allocated
+    pub(crate) fn rebuild_bom(&self, live: impl Iterator<Item = (u32, u32)>) {
+        self.fill(BOM_REU_ADDRESS, BOM_SIZE as u32, 0);
+        self.swap_bom_in();
+        for (address, len) in live {
+            let offset = ((address - REU_POOL_START) / ALLOCATION_UNIT as u32) as usize;
+            for i in offset..offset + as_blocks(len) {
+                unsafe { (*BOM).mark_occupied(i) };
+            }
+        }
+        self.swap_bom_out();
+    }
+
     fn swap_bom_in(&self) {
         // step aside, Kernal!
         unsafe {
             crate::__disable_mi();
             (*CPU_PORT).write(CpuPortFlags::RAM_IO_RAM);
         }
-        self.set_range(BOM_RAM_ADDRESS, BOM_REU_ADDRESS, BOM_SIZE);
-        self.swap();
+        self.swap(BOM_RAM_ADDRESS, BOM_REU_ADDRESS, BOM_SIZE);
     }
 
     fn swap_bom_out(&self) {
         // write modified BOM back to REU
-        self.swap();
+        self.swap(BOM_RAM_ADDRESS, BOM_REU_ADDRESS, BOM_SIZE);
         // restore KERNAL
         unsafe {
             (*CPU_PORT).write(CpuPortFlags::RAM_IO_KERNAL);
@@ -1,37 +1,105 @@
-use crate::ram_expansion_unit;
+use crate::expansion_memory::{self, ExpansionMemory, ReadMemory};
 use crate::reu_allocator::ReuChunk;
+use crate::reu_safe::ReuSafe;
 use core::cell::UnsafeCell;
-use core::mem;
-use core::ops::{Index, IndexMut};
+use core::cmp::Ordering;
+use core::mem::{self, ManuallyDrop};
+use core::ops::{Index, IndexMut, Range};
 
 extern "C" {
     fn malloc(n: usize) -> *mut u8;
     fn free(ptr: *mut u8);
 }
 
+/// How the cached window is placed around an index that missed the cache
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum WindowAlignment {
+    /// Pick `Forward`, `Backward` or `Centered` from the last access
+    Adaptive,
+    /// Window starts at the requested index, best for ascending scans
+    Forward,
+    /// Window ends at the requested index, best for descending scans
+    Backward,
+    /// Requested index is in the middle of the window, best for searches
+    Centered,
+    /// Window starts at a multiple of the window size
+    Aligned,
+}
+
 /// An array of T stored wholly in REU memory, swapped into RAM
 /// as required.
 ///
+/// Elements are `ReuSafe`, so they have no destructors: `pop` hands the
+/// element back by value, `truncate` and dropping the array just forget
+/// the elements and dropping frees both the RAM cache and the REU chunk.
+///
+/// An array prepared offline can also be read straight from any
+/// `ReadMemory`, e.g. cartridge flash, see `from_memory`.
+///
 /// To construct use `with_capacity`
 pub struct REUArray<T> {
     cache: UnsafeCell<*mut T>, // Pointer to the heap-allocated cache wrapped in UnsafeCell
     capacity: u32,             // Total number of elements in the remote data
     window_start_index: UnsafeCell<u32>, // The starting index of the current window in the remote data
+    window_len: UnsafeCell<usize>, // How many elements are currently loaded, 0 means nothing cached
     window_size: usize,
-    dirty: UnsafeCell<bool>, // Changed from bool to UnsafeCell<bool>
-    reu_chunk: ReuChunk,
+    alignment: WindowAlignment,
+    last_index: UnsafeCell<u32>, // Most recently accessed index, used by `WindowAlignment::Adaptive`
+    dirty_from: UnsafeCell<usize>, // First modified element in the window
+    dirty_to: UnsafeCell<usize>, // One past the last modified element, dirty_from == dirty_to means clean
+    reu_chunk: ManuallyDrop<ReuChunk>, // Owned unless the array is read-only
+    source: Option<&'static dyn ReadMemory>, // Memory of a read-only array, None for the selected expansion memory
     element_size: usize,
     element_count: u32,
 }
 
-impl<T> REUArray<T> {
+impl<T: ReuSafe> REUArray<T> {
     /// Main `REUArray` constructor.
     ///
     /// `capacity` - maximum capacity of the array as u32
     ///
-    /// `window_size` - how many elements are kept in RAM
+    /// `window_size` - how many elements are kept in RAM, at least one
     pub fn with_capacity(capacity: u32, window_size: usize) -> Self {
+        let element_size = mem::size_of::<T>() as u32;
+        let reu_chunk = expansion_memory::memory().alloc(capacity * element_size);
+        Self::with_chunk(reu_chunk, None, capacity, window_size)
+    }
+
+    /// Read-only array of `len` elements stored one after another at
+    /// `address` of `source`, with `window_size` of them kept in RAM
+    ///
+    /// This is for data prepared offline, e.g. on an `EasyFlash` cartridge.
+    /// Reading works like for any other array, modifying it panics.
+    pub fn from_memory(
+        source: &'static dyn ReadMemory,
+        address: u32,
+        len: u32,
+        window_size: usize,
+    ) -> Self {
+        let size = len * mem::size_of::<T>() as u32;
+        assert!(address + size <= source.size(), "array outside memory");
+        // never freed, as the array doesn't own it
+        let reu_chunk = unsafe { ReuChunk::from_raw(address, size) };
+        let mut array = Self::with_chunk(reu_chunk, Some(source), len, window_size);
+        array.element_count = len;
+        array
+    }
+
+    fn with_chunk(
+        reu_chunk: ReuChunk,
+        source: Option<&'static dyn ReadMemory>,
+        capacity: u32,
+        window_size: usize,
+    ) -> Self {
+        assert!(window_size > 0, "empty window");
         let element_size = mem::size_of::<T>();
+        // no point caching more than the whole array
+        // compared as u32, usize is 16 bits on the C64
+        let window_size = if capacity < window_size as u32 {
+            capacity as usize
+        } else {
+            window_size
+        };
         let cache_size = window_size * element_size;
 
         unsafe {
@@ -39,15 +107,19 @@ impl<T> REUArray<T> {
             if cache_ptr.is_null() {
                 panic!("out of memory");
             }
-            let reu_ptr = ram_expansion_unit::reu().alloc(capacity * element_size as u32);
 
             REUArray {
                 cache: UnsafeCell::new(cache_ptr),
                 capacity,
                 window_start_index: UnsafeCell::new(0), // start with the beginning of the remote data
+                window_len: UnsafeCell::new(0),
                 window_size,
-                dirty: UnsafeCell::new(false), // Initialize with UnsafeCell<bool>
-                reu_chunk: reu_ptr,
+                alignment: WindowAlignment::Adaptive,
+                last_index: UnsafeCell::new(0),
+                dirty_from: UnsafeCell::new(0),
+                dirty_to: UnsafeCell::new(0),
+                reu_chunk: ManuallyDrop::new(reu_chunk),
+                source,
                 element_size,
                 element_count: 0,
             }
@@ -58,36 +130,511 @@ impl<T> REUArray<T> {
     pub fn push(&mut self, element: T) {
         let i = self.element_count;
         if i < self.capacity {
-            self[i] = element;
+            // counted first, indexing checks against the length
             self.element_count += 1;
+            self[i] = element;
         }
     }
 
-    /// Obtain mutable iterator for `REUArray`
-    pub fn iter_mut(&mut self) -> REUArrayIterMut<T> {
-        REUArrayIterMut {
+    /// Remove the last element and return it
+    pub fn pop(&mut self) -> Option<T> {
+        if self.element_count == 0 {
+            None
+        } else {
+            self.element_count -= 1;
+            Some(*self.return_cached(self.element_count))
+        }
+    }
+
+    /// Shorten the array to `len` elements, elements past `len` are forgotten
+    pub fn truncate(&mut self, len: u32) {
+        if len < self.element_count {
+            self.element_count = len;
+        }
+    }
+
+    /// Grow or shrink the array to `len` elements, new elements are set to `value`
+    pub fn resize(&mut self, len: u32, value: T) {
+        assert!(len <= self.capacity, "capacity {}/{}", len, self.capacity);
+        let old_len = self.element_count;
+        self.element_count = len;
+        if len > old_len {
+            self.fill(old_len..len, value);
+        }
+    }
+
+    /// Number of elements in the array
+    pub fn len(&self) -> u32 {
+        self.element_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.element_count == 0
+    }
+
+    /// Maximum number of elements the array can hold
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    /// Choose how the window is placed when an index misses the cache
+    pub fn set_alignment(&mut self, alignment: WindowAlignment) {
+        self.alignment = alignment;
+    }
+
+    /// Append all `items` to the array with a single DMA transfer
+    pub fn extend_from_slice(&mut self, items: &[T]) {
+        let start = self.element_count;
+        let count = items.len() as u32;
+        assert!(
+            start + count <= self.capacity,
+            "capacity {}+{}/{}",
+            start,
+            count,
+            self.capacity
+        );
+        self.element_count += count;
+        self.copy_from_slice(start..start + count, items);
+    }
+
+    /// Overwrite elements in `range` with `src` using a single DMA transfer
+    pub fn copy_from_slice(&mut self, range: Range<u32>, src: &[T]) {
+        self.check_range(&range, src.len());
+        self.invalidate(&range);
+        self.reu_chunk.push_at(
+            self.writable_memory(),
+            src.as_ptr() as usize,
+            range.start * self.element_size as u32,
+            src.len() * self.element_size,
+        );
+    }
+
+    /// Copy elements in `range` into `dst` using a single DMA transfer
+    pub fn copy_to_slice(&self, range: Range<u32>, dst: &mut [T]) {
+        self.check_range(&range, dst.len());
+        // REU must see what was modified in the window
+        self.write_back();
+        self.pull_at(
+            dst.as_mut_ptr() as usize,
+            range.start * self.element_size as u32,
+            dst.len() * self.element_size,
+        );
+    }
+
+    /// Set all elements in `range` to `value`
+    ///
+    /// The window is filled with `value` once and then pushed over
+    /// the range page by page.
+    pub fn fill(&mut self, range: Range<u32>, value: T) {
+        self.check_range(&range, (range.end - range.start) as usize);
+        let reu = self.writable_memory();
+        self.write_back();
+        unsafe {
+            // the window no longer mirrors REU
+            *self.window_len.get() = 0;
+            let cache = *self.cache.get();
+            for i in 0..self.window_size {
+                cache.add(i).write(value);
+            }
+        }
+        let mut index = range.start;
+        while index < range.end {
+            let left = range.end - index;
+            let count = if left < self.window_size as u32 {
+                left as usize
+            } else {
+                self.window_size
+            };
+            unsafe {
+                self.reu_chunk.push_at(
+                    reu,
+                    *self.cache.get() as usize,
+                    index * self.element_size as u32,
+                    count * self.element_size,
+                );
+            }
+            index += count as u32;
+        }
+    }
+
+    /// Sort the array with a comparator function, keeping equal elements in order
+    ///
+    /// Each page of `window_size` elements is sorted in RAM first, then the
+    /// pages are merged pass by pass between the array chunk and an REU
+    /// scratch chunk of the same size. The window is split into three
+    /// merge buffers, so it has to hold at least 3 elements.
+    pub fn sort_by<F>(&mut self, mut compare: F)
+    where
+        F: FnMut(&T, &T) -> Ordering,
+    {
+        let len = self.element_count;
+        if len < 2 {
+            return;
+        }
+        let page = self.window_size as u32;
+        // the merge passes split the cache into three buffers
+        assert!(
+            len <= page || self.window_size >= 3,
+            "window too small to sort"
+        );
+
+        // sort every page in RAM
+        let mut start = 0;
+        while start < len {
+            let count = if len - start < page {
+                (len - start) as usize
+            } else {
+                self.window_size
+            };
+            self.load_window(start);
+            let items = unsafe { core::slice::from_raw_parts_mut(*self.cache.get(), count) };
+            insertion_sort(items, &mut compare);
+            self.mark_dirty(start);
+            self.mark_dirty(start + count as u32 - 1);
+            start += count as u32;
+        }
+        self.write_back();
+        if len <= page {
+            return;
+        }
+
+        // merge sorted runs, the cache becomes three merge buffers
+        unsafe {
+            *self.window_len.get() = 0;
+        }
+        let buffer_size = self.window_size / 3;
+        let cache = unsafe { *self.cache.get() };
+        let out = unsafe { cache.add(2 * buffer_size) };
+
+        let reu = self.writable_memory();
+        let mut scratch = reu.alloc(self.reu_chunk.len());
+        let mut in_scratch = false;
+        let mut run = page;
+
+        while run < len {
+            let (src, dst) = if in_scratch {
+                (&scratch, &*self.reu_chunk)
+            } else {
+                (&*self.reu_chunk, &scratch)
+            };
+            let mut written = 0;
+            let mut lo = 0;
+            while lo < len {
+                let mid = if len - lo < run { len } else { lo + run };
+                let hi = if len - mid < run { len } else { mid + run };
+                let mut left = SortRun::new(cache, buffer_size, lo, mid);
+                let mut right =
+                    SortRun::new(unsafe { cache.add(buffer_size) }, buffer_size, mid, hi);
+                let mut out_len = 0;
+
+                loop {
+                    let take_left = match (left.head(src), right.head(src)) {
+                        (Some(l), Some(r)) => unsafe { compare(&*r, &*l) != Ordering::Less },
+                        (Some(_), None) => true,
+                        (None, Some(_)) => false,
+                        (None, None) => break,
+                    };
+                    let item = if take_left { left.take() } else { right.take() };
+                    unsafe {
+                        out.add(out_len).write(item);
+                    }
+                    out_len += 1;
+                    if out_len == buffer_size {
+                        dst.push_at(
+                            reu,
+                            out as usize,
+                            written * self.element_size as u32,
+                            out_len * self.element_size,
+                        );
+                        written += out_len as u32;
+                        out_len = 0;
+                    }
+                }
+                if out_len > 0 {
+                    dst.push_at(
+                        reu,
+                        out as usize,
+                        written * self.element_size as u32,
+                        out_len * self.element_size,
+                    );
+                    written += out_len as u32;
+                }
+                lo = hi;
+            }
+            in_scratch = !in_scratch;
+            run = if len - run < run { len } else { run * 2 };
+        }
+
+        if in_scratch {
+            // sorted data ended up in scratch, keep it and free the old chunk
+            mem::swap(&mut *self.reu_chunk, &mut scratch);
+        }
+    }
+
+    /// Sort the array by a key extracted from each element
+    pub fn sort_by_key<K, F>(&mut self, mut f: F)
+    where
+        F: FnMut(&T) -> K,
+        K: Ord,
+    {
+        self.sort_by(|a, b| f(a).cmp(&f(b)));
+    }
+
+    /// Binary search a sorted array with a comparator function
+    ///
+    /// Probes single elements until the remaining range fits in the window,
+    /// then pulls that range in once and finishes the search in RAM.
+    pub fn binary_search_by<F>(&self, mut f: F) -> Result<u32, u32>
+    where
+        F: FnMut(&T) -> Ordering,
+    {
+        let mut low = 0;
+        let mut high = self.element_count;
+        while high - low > self.window_size as u32 {
+            let mid = low + (high - low) / 2;
+            match f(&self[mid]) {
+                Ordering::Less => low = mid + 1,
+                Ordering::Greater => high = mid,
+                Ordering::Equal => return Ok(mid),
+            }
+        }
+        if low == high {
+            return Err(low);
+        }
+
+        unsafe {
+            let start = *self.window_start_index.get();
+            if low < start || high > start + *self.window_len.get() as u32 {
+                self.load_window(self.clamp_window_start(low));
+            }
+            let start = *self.window_start_index.get();
+            let items = core::slice::from_raw_parts(
+                (*self.cache.get()).add((low - start) as usize),
+                (high - low) as usize,
+            );
+            match items.binary_search_by(f) {
+                Ok(i) => Ok(low + i as u32),
+                Err(i) => Err(low + i as u32),
+            }
+        }
+    }
+
+    /// Binary search a sorted array by a key extracted from each element
+    pub fn binary_search_by_key<K, F>(&self, key: &K, mut f: F) -> Result<u32, u32>
+    where
+        F: FnMut(&T) -> K,
+        K: Ord,
+    {
+        self.binary_search_by(|element| f(element).cmp(key))
+    }
+
+    /// Write modified elements of the cached window back to REU
+    pub fn flush(&mut self) {
+        self.write_back();
+    }
+
+    /// Obtain iterator over copies of the elements
+    ///
+    /// The window may move while iterating, so elements are handed out by value.
+    pub fn iter(&self) -> REUArrayIter<T> {
+        self.iter_range(0..self.element_count)
+    }
+
+    /// Obtain iterator over copies of elements `range.start..range.end`
+    pub fn iter_range(&self, range: Range<u32>) -> REUArrayIter<T> {
+        self.check_range_bounds(&range);
+        REUArrayIter {
             reu_array: self,
-            index: 0,
+            index: range.start,
+            end: range.end,
+        }
+    }
+
+    /// Call `f` with each page of up to `window_size` elements as a mutable
+    /// slice of the cache, so each page can be processed at RAM speed
+    ///
+    /// Every page handed out is written back to REU.
+    pub fn for_each_chunk_mut(&mut self, mut f: impl FnMut(&mut [T])) {
+        let mut start = 0;
+        while start < self.element_count {
+            let count = (self.element_count - start).min(self.window_size as u32) as usize;
+            unsafe {
+                if *self.window_start_index.get() != start || *self.window_len.get() < count {
+                    self.load_window(start);
+                }
+            }
+            self.mark_dirty(start);
+            self.mark_dirty(start + count as u32 - 1);
+            f(unsafe { core::slice::from_raw_parts_mut(*self.cache.get(), count) });
+            start += count as u32;
+        }
+    }
+
+    /// Call `f` with each element in order
+    ///
+    /// Every element handed to `f` is treated as modified. It's in the cached
+    /// window while `f` runs, so the write always reaches REU.
+    pub fn for_each_mut(&mut self, f: impl FnMut(&mut T)) {
+        self.for_each_mut_range(0..self.element_count, f);
+    }
+
+    /// Call `f` with each element `range.start..range.end` in order, see `for_each_mut`
+    pub fn for_each_mut_range(&mut self, range: Range<u32>, mut f: impl FnMut(&mut T)) {
+        self.check_range_bounds(&range);
+        for index in range {
+            let element = self.cached_ptr(index);
+            self.mark_dirty(index);
+            f(unsafe { &mut *element });
+        }
+    }
+
+    /// Call `f` with each element from last to first, see `for_each_mut`
+    pub fn for_each_mut_rev(&mut self, f: impl FnMut(&mut T)) {
+        self.for_each_mut_range_rev(0..self.element_count, f);
+    }
+
+    /// Call `f` with each element `range.start..range.end` from last to first, see `for_each_mut`
+    pub fn for_each_mut_range_rev(&mut self, range: Range<u32>, mut f: impl FnMut(&mut T)) {
+        self.check_range_bounds(&range);
+        for index in range.rev() {
+            let element = self.cached_ptr(index);
+            self.mark_dirty(index);
+            f(unsafe { &mut *element });
         }
     }
 
     fn ensure_in_cache(&self, index: u32) {
-        let window_start_index = unsafe { *self.window_start_index.get() };
+        unsafe {
+            let window_start_index = *self.window_start_index.get();
+            let window_len = *self.window_len.get() as u32;
 
-        if index < window_start_index || index >= window_start_index + self.window_size as u32 {
-            unsafe {
-                if *self.dirty.get() {
-                    self.prepare_slice();
-                    ram_expansion_unit::reu().push();
-                    *self.dirty.get() = false;
+            if index < window_start_index || index >= window_start_index + window_len {
+                self.load_window(self.window_start_for(index));
+            }
+            *self.last_index.get() = index;
+        }
+    }
+
+    /// Write back the current window and pull in a new one at `start`
+    fn load_window(&self, start: u32) {
+        self.write_back();
+        unsafe {
+            *self.window_start_index.get() = start;
+            // never read past the end of the REU chunk, in u32 as usize is 16 bits
+            *self.window_len.get() = (self.capacity - start).min(self.window_size as u32) as usize;
+            self.pull_slice(0, *self.window_len.get());
+        }
+    }
+
+    fn cached_ptr(&self, index: u32) -> *mut T {
+        self.ensure_in_cache(index);
+        unsafe {
+            let index_in_window = (index - *self.window_start_index.get()) as usize;
+            (*self.cache.get()).add(index_in_window)
+        }
+    }
+
+    /// Where a new window containing `index` should start
+    fn window_start_for(&self, index: u32) -> u32 {
+        let size = self.window_size as u32;
+        let alignment = match self.alignment {
+            WindowAlignment::Adaptive => {
+                let last = unsafe { *self.last_index.get() };
+                if index == last.wrapping_add(1) {
+                    WindowAlignment::Forward
+                } else if index.wrapping_add(1) == last {
+                    WindowAlignment::Backward
+                } else {
+                    WindowAlignment::Centered
                 }
-                *self.window_start_index.get() = index;
-                self.prepare_slice();
-                ram_expansion_unit::reu().pull();
             }
+            other => other,
+        };
+        let start = match alignment {
+            WindowAlignment::Backward => (index + 1).saturating_sub(size),
+            WindowAlignment::Centered => index.saturating_sub(size / 2),
+            WindowAlignment::Aligned => index - index % size,
+            _ => index,
+        };
+        // keep the window inside the chunk, but it must still contain `index`
+        self.clamp_window_start(start)
+    }
+
+    fn clamp_window_start(&self, start: u32) -> u32 {
+        let last_start = self.capacity - self.window_size as u32;
+        if start > last_start {
+            last_start
+        } else {
+            start
         }
     }
 
+    /// Push only the modified span of the window back into REU
+    fn write_back(&self) {
+        unsafe {
+            let from = *self.dirty_from.get();
+            let to = *self.dirty_to.get();
+            if from < to {
+                self.push_slice(from, to - from);
+                *self.dirty_from.get() = 0;
+                *self.dirty_to.get() = 0;
+            }
+        }
+    }
+
+    /// Extend the dirty span so it covers `index`, which must be in the window
+    fn mark_dirty(&self, index: u32) {
+        assert!(self.source.is_none(), "read-only array");
+        unsafe {
+            let index_in_window = (index - *self.window_start_index.get()) as usize;
+            let from = *self.dirty_from.get();
+            let to = *self.dirty_to.get();
+            if from == to {
+                *self.dirty_from.get() = index_in_window;
+                *self.dirty_to.get() = index_in_window + 1;
+            } else if index_in_window < from {
+                *self.dirty_from.get() = index_in_window;
+            } else if index_in_window >= to {
+                *self.dirty_to.get() = index_in_window + 1;
+            }
+        }
+    }
+
+    /// Flush the window and drop it if it overlaps `range`, which is about to
+    /// be overwritten in REU
+    fn invalidate(&self, range: &Range<u32>) {
+        self.write_back();
+        unsafe {
+            let start = *self.window_start_index.get();
+            let end = start + *self.window_len.get() as u32;
+            if range.start < end && start < range.end {
+                *self.window_len.get() = 0;
+            }
+        }
+    }
+
+    fn check_range(&self, range: &Range<u32>, len: usize) {
+        assert!(
+            range.start <= range.end
+                && range.end <= self.element_count
+                && range.end - range.start == len as u32,
+            "range {}..{}/{}",
+            range.start,
+            range.end,
+            self.element_count
+        );
+    }
+
+    fn check_range_bounds(&self, range: &Range<u32>) {
+        assert!(
+            range.start <= range.end && range.end <= self.element_count,
+            "range {}..{}/{}",
+            range.start,
+            range.end,
+            self.element_count
+        );
+    }
+
     fn check_bounds(&self, index: u32) {
         assert!(
             index < self.element_count,
@@ -98,63 +645,182 @@ impl<T> REUArray<T> {
     }
 
     fn return_cached(&self, index: u32) -> &mut T {
+        unsafe { &mut *self.cached_ptr(index) }
+    }
+
+    /// Push `count` elements starting at `from` within the window into REU
+    fn push_slice(&self, from: usize, count: usize) {
         unsafe {
-            self.ensure_in_cache(index);
-            let index_in_window = (index - *self.window_start_index.get()) as isize;
-            let offset_ptr = (*self.cache.get()).offset(index_in_window);
-            &mut *offset_ptr // Correctly dereference the raw pointer to get a mutable reference
+            let first = *self.window_start_index.get() + from as u32;
+            self.reu_chunk.push_at(
+                self.writable_memory(),
+                (*self.cache.get()).add(from) as usize,
+                first * self.element_size as u32,
+                count * self.element_size,
+            );
         }
     }
 
-    fn prepare_slice(&self) {
+    /// Pull `count` elements starting at `from` within the window from REU
+    fn pull_slice(&self, from: usize, count: usize) {
         unsafe {
-            let byte_count = self.element_size * self.window_size;
-            ram_expansion_unit::reu().set_range(
-                *self.cache.get() as usize,
-                self.reu_chunk.address + *self.window_start_index.get() * self.element_size as u32,
-                byte_count,
+            let first = *self.window_start_index.get() + from as u32;
+            self.pull_at(
+                (*self.cache.get()).add(from) as usize,
+                first * self.element_size as u32,
+                count * self.element_size,
             );
         }
     }
+
+    /// Pull `length` bytes starting `offset` bytes into the array's data into C64 RAM
+    fn pull_at(&self, c64_start: usize, offset: u32, length: usize) {
+        match self.source {
+            // bounds were checked by the array
+            Some(source) if length > 0 => {
+                source.read(c64_start, self.reu_chunk.address + offset, length)
+            }
+            Some(_) => {}
+            None => self
+                .reu_chunk
+                .pull_at(expansion_memory::memory(), c64_start, offset, length),
+        }
+    }
+
+    /// The selected expansion memory holding the array, panics if it's read-only
+    fn writable_memory(&self) -> &'static dyn ExpansionMemory {
+        assert!(self.source.is_none(), "read-only array");
+        expansion_memory::memory()
+    }
 }
 
-pub struct REUArrayIterMut<'a, T> {
-    reu_array: &'a mut REUArray<T>,
+pub struct REUArrayIter<'a, T> {
+    reu_array: &'a REUArray<T>,
     index: u32,
+    end: u32,
 }
 
-impl<'a, T> Iterator for REUArrayIterMut<'a, T> {
-    type Item = &'a mut T;
+impl<'a, T: ReuSafe> Iterator for REUArrayIter<'a, T> {
+    type Item = T;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.index < self.reu_array.element_count {
-            self.reu_array.ensure_in_cache(self.index);
-
-            // Temporarily split the borrow to avoid issues with mutable references
-            let window_start_index = unsafe { *self.reu_array.window_start_index.get() };
-            let index_in_window = self.index - window_start_index;
-            let item = unsafe {
-                let cache_ptr = *self.reu_array.cache.get();
-                let item_ptr = cache_ptr.add(index_in_window as usize);
-                &mut *item_ptr
-            };
+        if self.index < self.end {
+            let item = unsafe { *self.reu_array.cached_ptr(self.index) };
             self.index += 1;
             Some(item)
         } else {
             None
         }
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        size_hint(self.end - self.index)
+    }
+}
+
+impl<'a, T: ReuSafe> DoubleEndedIterator for REUArrayIter<'a, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.index < self.end {
+            self.end -= 1;
+            Some(unsafe { *self.reu_array.cached_ptr(self.end) })
+        } else {
+            None
+        }
+    }
+}
+
+/// Iterator size hint for `left` elements, which may be more than `usize` holds
+fn size_hint(left: u32) -> (usize, Option<usize>) {
+    match usize::try_from(left) {
+        Ok(left) => (left, Some(left)),
+        Err(_) => (usize::MAX, None),
+    }
+}
+
+/// Stable in-place sort for a single page, small and good enough for
+/// window-sized slices
+fn insertion_sort<T: Copy, F>(items: &mut [T], compare: &mut F)
+where
+    F: FnMut(&T, &T) -> Ordering,
+{
+    for i in 1..items.len() {
+        let item = items[i];
+        let mut j = i;
+        while j > 0 && compare(&item, &items[j - 1]) == Ordering::Less {
+            items[j] = items[j - 1];
+            j -= 1;
+        }
+        items[j] = item;
+    }
+}
+
+/// One sorted run read from REU through a small RAM buffer while merging
+struct SortRun<T> {
+    buffer: *mut T,
+    buffer_size: usize,
+    next: u32, // next element to pull from REU
+    end: u32,
+    pos: usize,
+    len: usize,
+}
+
+impl<T: ReuSafe> SortRun<T> {
+    fn new(buffer: *mut T, buffer_size: usize, start: u32, end: u32) -> Self {
+        SortRun {
+            buffer,
+            buffer_size,
+            next: start,
+            end,
+            pos: 0,
+            len: 0,
+        }
+    }
+
+    /// Current element of the run, refilling the buffer when it's used up
+    fn head(&mut self, chunk: &ReuChunk) -> Option<*const T> {
+        if self.pos == self.len {
+            if self.next == self.end {
+                return None;
+            }
+            let left = self.end - self.next;
+            let count = if left < self.buffer_size as u32 {
+                left as usize
+            } else {
+                self.buffer_size
+            };
+            let element_size = mem::size_of::<T>();
+            chunk.pull_at(
+                expansion_memory::memory(),
+                self.buffer as usize,
+                self.next * element_size as u32,
+                count * element_size,
+            );
+            self.next += count as u32;
+            self.pos = 0;
+            self.len = count;
+        }
+        Some(unsafe { self.buffer.add(self.pos) })
+    }
+
+    fn take(&mut self) -> T {
+        let item = unsafe { *self.buffer.add(self.pos) };
+        self.pos += 1;
+        item
+    }
 }
 
 impl<'a, T> Drop for REUArray<T> {
     fn drop(&mut self) {
         unsafe {
             free(*self.cache.get() as *mut u8);
+            if self.source.is_none() {
+                ManuallyDrop::drop(&mut self.reu_chunk);
+            }
         }
     }
 }
 
-impl<T> Index<u32> for REUArray<T> {
+impl<T: ReuSafe> Index<u32> for REUArray<T> {
     type Output = T;
     fn index(&self, index: u32) -> &Self::Output {
         #[cfg(debug_assertions)]
@@ -163,14 +829,13 @@ impl<T> Index<u32> for REUArray<T> {
     }
 }
 
-impl<T> IndexMut<u32> for REUArray<T> {
+impl<T: ReuSafe> IndexMut<u32> for REUArray<T> {
     fn index_mut(&mut self, index: u32) -> &mut Self::Output {
         #[cfg(debug_assertions)]
         self.check_bounds(index);
-        unsafe {
-            *self.dirty.get() = true;
-        }
-        self.return_cached(index)
+        let element = self.return_cached(index);
+        self.mark_dirty(index);
+        element
     }
 }
 
@@ -189,10 +854,13 @@ impl<T> ufmt::uDebug for REUArray<T> {
         f.write_str("\nwindow=")?;
         unsafe {
             let ws = *self.window_start_index.get();
-            let dr = *self.dirty.get();
             ws.fmt(f)?;
+            f.write_char('+')?;
+            (*self.window_len.get()).fmt(f)?;
             f.write_str(" dirty=")?;
-            dr.fmt(f)?;
+            (*self.dirty_from.get()).fmt(f)?;
+            f.write_char('-')?;
+            (*self.dirty_to.get()).fmt(f)?;
         }
         Ok(())
     }
@@ -0,0 +1,177 @@
+use crate::expansion_memory;
+use crate::reu_allocator::ReuChunk;
+use core::ops::Range;
+
+/// Bytes pulled per DMA by `count_range`
+const PAGE: usize = 256;
+
+/// A fixed-size set of bits stored in REU memory
+///
+/// Meant for large masks such as fog of war or visited flags. Single bit
+/// operations transfer just the byte holding the bit. `set_range` and
+/// `clear_range` set the whole bytes with the memory's DMA fill and only
+/// read-modify-write the bytes at either end, `count_range` pulls the
+/// bytes page by page.
+///
+/// To construct use `new`
+pub struct REUBitSet {
+    chunk: ReuChunk,
+    len: u32,
+}
+
+impl REUBitSet {
+    /// Allocate `len` bits in REU, all clear
+    pub fn new(len: u32) -> Self {
+        let set = REUBitSet {
+            // an empty set still gets a byte, the allocator can't hand out 0
+            chunk: expansion_memory::memory().alloc(len.div_ceil(8).max(1)),
+            len,
+        };
+        set.fill_bytes(0, set.chunk.len(), 0);
+        set
+    }
+
+    /// Number of bits in the set
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Check whether bit `index` is set
+    pub fn test(&self, index: u32) -> bool {
+        self.check_index(index);
+        let byte: u8 = self.chunk.read(index / 8);
+        byte & mask(index) != 0
+    }
+
+    pub fn set(&mut self, index: u32) {
+        self.update_byte(index, |byte| byte | mask(index));
+    }
+
+    pub fn clear(&mut self, index: u32) {
+        self.update_byte(index, |byte| byte & !mask(index));
+    }
+
+    /// Flip bit `index`, returns its new state
+    pub fn toggle(&mut self, index: u32) -> bool {
+        self.update_byte(index, |byte| byte ^ mask(index)) & mask(index) != 0
+    }
+
+    /// Set all bits in `range`
+    pub fn set_range(&mut self, range: Range<u32>) {
+        self.assign_range(range, true);
+    }
+
+    /// Clear all bits in `range`
+    pub fn clear_range(&mut self, range: Range<u32>) {
+        self.assign_range(range, false);
+    }
+
+    /// Clear the whole set
+    pub fn clear_all(&mut self) {
+        self.fill_bytes(0, self.chunk.len(), 0);
+    }
+
+    /// Number of set bits
+    pub fn count(&self) -> u32 {
+        self.count_range(0..self.len)
+    }
+
+    /// Number of set bits in `range`
+    pub fn count_range(&self, range: Range<u32>) -> u32 {
+        self.check_range(&range);
+        if range.start == range.end {
+            return 0;
+        }
+        let first = range.start / 8;
+        let last = (range.end - 1) / 8;
+        let mut buffer = [0u8; PAGE];
+        let mut count = 0;
+        let mut byte_index = first;
+        while byte_index <= last {
+            let left = (last + 1 - byte_index) as usize;
+            let n = if left < PAGE { left } else { PAGE };
+            self.chunk.pull_at(
+                expansion_memory::memory(),
+                buffer.as_mut_ptr() as usize,
+                byte_index,
+                n,
+            );
+            for (i, byte) in buffer[..n].iter().enumerate() {
+                let at = byte_index + i as u32;
+                let mut bits = *byte;
+                if at == first {
+                    bits &= 0xFF << (range.start % 8);
+                }
+                if at == last {
+                    bits &= 0xFF >> (7 - (range.end - 1) % 8);
+                }
+                count += bits.count_ones();
+            }
+            byte_index += n as u32;
+        }
+        count
+    }
+
+    fn assign_range(&mut self, range: Range<u32>, value: bool) {
+        self.check_range(&range);
+        if range.start == range.end {
+            return;
+        }
+        let first = range.start / 8;
+        let last = (range.end - 1) / 8;
+        // bits of the end bytes that fall inside the range
+        let mut first_mask = 0xFFu8 << (range.start % 8);
+        let last_mask = 0xFFu8 >> (7 - (range.end - 1) % 8);
+        if first == last {
+            first_mask &= last_mask;
+        }
+        let assign = |byte: u8, mask: u8| if value { byte | mask } else { byte & !mask };
+
+        self.update_byte(first * 8, |byte| assign(byte, first_mask));
+        if last > first {
+            self.fill_bytes(first + 1, last, if value { 0xFF } else { 0 });
+            self.update_byte(last * 8, |byte| assign(byte, last_mask));
+        }
+    }
+
+    /// Read-modify-write the byte holding bit `index`, returns the new byte
+    fn update_byte(&mut self, index: u32, f: impl FnOnce(u8) -> u8) -> u8 {
+        self.check_index(index);
+        let byte: u8 = f(self.chunk.read(index / 8));
+        self.chunk.write(index / 8, &byte);
+        byte
+    }
+
+    /// Set bytes `from..to` of the chunk to `value` with the memory's own fill
+    fn fill_bytes(&self, from: u32, to: u32, value: u8) {
+        assert!(
+            from <= to && to <= self.chunk.len(),
+            "bytes {}..{}",
+            from,
+            to
+        );
+        expansion_memory::memory().fill(self.chunk.address + from, to - from, value);
+    }
+
+    fn check_index(&self, index: u32) {
+        assert!(index < self.len, "bit {}/{}", index, self.len);
+    }
+
+    fn check_range(&self, range: &Range<u32>) {
+        assert!(
+            range.start <= range.end && range.end <= self.len,
+            "bits {}..{}/{}",
+            range.start,
+            range.end,
+            self.len
+        );
+    }
+}
+
+fn mask(index: u32) -> u8 {
+    1 << (index % 8)
+}
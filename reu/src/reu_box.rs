@@ -0,0 +1,175 @@
+use crate::expansion_memory;
+use crate::reu_allocator::ReuChunk;
+use crate::reu_safe::ReuSafe;
+use core::marker::PhantomData;
+use core::mem::size_of;
+use core::ops::{Deref, DerefMut};
+use core::slice;
+
+extern "C" {
+    fn malloc(n: usize) -> *mut u8;
+    fn free(ptr: *mut u8);
+}
+
+/// A single value of T stored in REU memory
+///
+/// Meant for big singletons like level maps or save states. Access goes
+/// through guards that pull the value into a heap buffer in RAM; a mutable
+/// guard pushes it back on drop, but only if it was actually written to.
+///
+/// To construct use `new`
+pub struct REUBox<T: ReuSafe> {
+    chunk: ReuChunk,
+    _marker: PhantomData<T>,
+}
+
+impl<T: ReuSafe> REUBox<T> {
+    /// Allocate `size_of::<T>()` bytes in REU and move `value` there
+    pub fn new(value: T) -> Self {
+        let chunk = expansion_memory::memory().alloc(size_of::<T>() as u32);
+        chunk.write(0, &value);
+        REUBox {
+            chunk,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Copy the value into RAM, it has to fit on the stack
+    pub fn get(&self) -> T {
+        self.chunk.read(0)
+    }
+
+    /// Replace the value
+    pub fn set(&mut self, value: T) {
+        self.chunk.write(0, &value);
+    }
+
+    /// Pull the value into RAM for reading
+    pub fn borrow(&self) -> REUBoxRef<T> {
+        REUBoxRef {
+            buffer: self.pull(),
+            _box: PhantomData,
+        }
+    }
+
+    /// Pull the value into RAM for modification
+    pub fn borrow_mut(&mut self) -> REUBoxMut<T> {
+        REUBoxMut {
+            buffer: self.pull(),
+            reu_box: self,
+            dirty: false,
+        }
+    }
+
+    /// Call `f` with the value pulled into RAM
+    pub fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        f(&self.borrow())
+    }
+
+    /// Call `f` with the value pulled into RAM, then push it back if `f` changed it
+    ///
+    /// The RAM copy is compared with REU through a small buffer on the stack
+    /// afterwards, which is cheaper than pushing an unchanged value back.
+    pub fn with_mut<R>(&mut self, f: impl FnOnce(&mut T) -> R) -> R {
+        let buffer = self.pull();
+        let result = f(unsafe { &mut *buffer });
+        unsafe {
+            if self.differs(buffer) {
+                self.chunk.push(expansion_memory::memory(), buffer as usize);
+            }
+            free(buffer as *mut u8);
+        }
+        result
+    }
+
+    /// Whether the value at `buffer` differs from the one in REU
+    fn differs(&self, buffer: *const T) -> bool {
+        let value = unsafe { slice::from_raw_parts(buffer as *const u8, size_of::<T>()) };
+        let mut stored = [0u8; 32];
+        let mut done = 0;
+        while done < value.len() {
+            let count = (value.len() - done).min(stored.len());
+            self.chunk.pull_at(
+                expansion_memory::memory(),
+                stored.as_mut_ptr() as usize,
+                done as u32,
+                count,
+            );
+            if stored[..count] != value[done..done + count] {
+                return true;
+            }
+            done += count;
+        }
+        false
+    }
+
+    fn pull(&self) -> *mut T {
+        unsafe {
+            let buffer = malloc(size_of::<T>()) as *mut T;
+            if buffer.is_null() {
+                panic!("out of memory");
+            }
+            self.chunk.pull(expansion_memory::memory(), buffer as usize);
+            buffer
+        }
+    }
+}
+
+/// Read access to the value of an `REUBox`, the RAM copy is freed on drop
+pub struct REUBoxRef<'a, T: ReuSafe> {
+    buffer: *mut T,
+    _box: PhantomData<&'a REUBox<T>>,
+}
+
+impl<'a, T: ReuSafe> Deref for REUBoxRef<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.buffer }
+    }
+}
+
+impl<'a, T: ReuSafe> Drop for REUBoxRef<'a, T> {
+    fn drop(&mut self) {
+        unsafe {
+            free(self.buffer as *mut u8);
+        }
+    }
+}
+
+/// Write access to the value of an `REUBox`
+///
+/// The RAM copy is pushed back to REU on drop if it was mutably dereferenced.
+pub struct REUBoxMut<'a, T: ReuSafe> {
+    buffer: *mut T,
+    reu_box: &'a mut REUBox<T>,
+    dirty: bool,
+}
+
+impl<'a, T: ReuSafe> Deref for REUBoxMut<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.buffer }
+    }
+}
+
+impl<'a, T: ReuSafe> DerefMut for REUBoxMut<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.dirty = true;
+        unsafe { &mut *self.buffer }
+    }
+}
+
+impl<'a, T: ReuSafe> Drop for REUBoxMut<'a, T> {
+    fn drop(&mut self) {
+        unsafe {
+            if self.dirty {
+                self.reu_box
+                    .chunk
+                    .push(expansion_memory::memory(), self.buffer as usize);
+            }
+            free(self.buffer as *mut u8);
+        }
+    }
+}
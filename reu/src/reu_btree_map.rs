@@ -0,0 +1,646 @@
+extern crate alloc;
+
+use crate::expansion_memory;
+use crate::reu_allocator::ReuChunk;
+use crate::reu_safe::ReuSafe;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::marker::PhantomData;
+use core::mem::size_of;
+use core::ops::{Bound, RangeBounds};
+use core::ptr;
+
+/// Every node is one REU page
+const NODE_SIZE: usize = 256;
+/// Node page starts with key count and leaf flag
+const HEADER: usize = 2;
+/// Nodes are allocated from REU in segments of this many pages
+const SEGMENT_NODES: u16 = 64;
+const NO_NODE: u16 = u16::MAX;
+
+/// A B-tree node pulled into RAM
+///
+/// Page layout: key count, leaf flag, `MAX` keys, `MAX` values and
+/// `MAX + 1` child node numbers.
+struct Node<K, V> {
+    index: u16,
+    page: [u8; NODE_SIZE],
+    _marker: PhantomData<(K, V)>,
+}
+
+impl<K: ReuSafe + Ord, V: ReuSafe> Node<K, V> {
+    /// Keys per node, odd so a full node splits around its median
+    const MAX: usize = {
+        let fit = (NODE_SIZE - HEADER - 2) / (size_of::<K>() + size_of::<V>() + 2);
+        if fit & 1 == 0 && fit > 0 {
+            fit - 1
+        } else {
+            fit
+        }
+    };
+    /// Keys every node but the root keeps
+    const MIN: usize = Self::MAX / 2;
+    const VALUES: usize = HEADER + Self::MAX * size_of::<K>();
+    const CHILDREN: usize = Self::VALUES + Self::MAX * size_of::<V>();
+
+    fn len(&self) -> usize {
+        self.page[0] as usize
+    }
+
+    fn set_len(&mut self, len: usize) {
+        self.page[0] = len as u8;
+    }
+
+    fn is_leaf(&self) -> bool {
+        self.page[1] != 0
+    }
+
+    fn key(&self, i: usize) -> K {
+        unsafe { ptr::read_unaligned(self.at(HEADER + i * size_of::<K>()) as *const K) }
+    }
+
+    fn set_key(&mut self, i: usize, key: K) {
+        unsafe { ptr::write_unaligned(self.at(HEADER + i * size_of::<K>()) as *mut K, key) }
+    }
+
+    fn value(&self, i: usize) -> V {
+        unsafe { ptr::read_unaligned(self.at(Self::VALUES + i * size_of::<V>()) as *const V) }
+    }
+
+    fn set_value(&mut self, i: usize, value: V) {
+        unsafe { ptr::write_unaligned(self.at(Self::VALUES + i * size_of::<V>()) as *mut V, value) }
+    }
+
+    fn child(&self, i: usize) -> u16 {
+        let at = Self::CHILDREN + i * 2;
+        u16::from_le_bytes([self.page[at], self.page[at + 1]])
+    }
+
+    fn set_child(&mut self, i: usize, child: u16) {
+        let at = Self::CHILDREN + i * 2;
+        self.page[at..at + 2].copy_from_slice(&child.to_le_bytes());
+    }
+
+    /// `Ok` with the position of `key` or `Err` with the child it would be under
+    fn search(&self, key: &K) -> Result<usize, usize> {
+        let mut low = 0;
+        let mut high = self.len();
+        while low < high {
+            let mid = (low + high) / 2;
+            match self.key(mid).cmp(key) {
+                Ordering::Less => low = mid + 1,
+                Ordering::Greater => high = mid,
+                Ordering::Equal => return Ok(mid),
+            }
+        }
+        Err(low)
+    }
+
+    /// Insert an entry at `i`, shifting the following entries right
+    fn insert_at(&mut self, i: usize, key: K, value: V) {
+        let len = self.len();
+        self.shift(
+            HEADER + i * size_of::<K>(),
+            (len - i) * size_of::<K>(),
+            true,
+            size_of::<K>(),
+        );
+        self.shift(
+            Self::VALUES + i * size_of::<V>(),
+            (len - i) * size_of::<V>(),
+            true,
+            size_of::<V>(),
+        );
+        self.set_key(i, key);
+        self.set_value(i, value);
+        self.set_len(len + 1);
+    }
+
+    /// Remove the entry at `i`, shifting the following entries left
+    fn remove_at(&mut self, i: usize) -> (K, V) {
+        let entry = (self.key(i), self.value(i));
+        let len = self.len();
+        self.shift(
+            HEADER + (i + 1) * size_of::<K>(),
+            (len - i - 1) * size_of::<K>(),
+            false,
+            size_of::<K>(),
+        );
+        self.shift(
+            Self::VALUES + (i + 1) * size_of::<V>(),
+            (len - i - 1) * size_of::<V>(),
+            false,
+            size_of::<V>(),
+        );
+        self.set_len(len - 1);
+        entry
+    }
+
+    /// Insert a child at `i`, call after `insert_at` grew the node
+    fn insert_child(&mut self, i: usize, child: u16) {
+        self.shift(Self::CHILDREN + i * 2, (self.len() - i) * 2, true, 2);
+        self.set_child(i, child);
+    }
+
+    /// Remove the child at `i`, call after `remove_at` shrank the node
+    fn remove_child(&mut self, i: usize) {
+        self.shift(
+            Self::CHILDREN + (i + 1) * 2,
+            (self.len() + 1 - i) * 2,
+            false,
+            2,
+        );
+    }
+
+    /// Move `count` bytes at `from` by `by` bytes to the right or left
+    fn shift(&mut self, from: usize, count: usize, right: bool, by: usize) {
+        let to = if right { from + by } else { from - by };
+        self.page.copy_within(from..from + count, to);
+    }
+
+    fn at(&self, offset: usize) -> *mut u8 {
+        self.page.as_ptr().wrapping_add(offset) as *mut u8
+    }
+}
+
+/// An ordered map stored in REU memory
+///
+/// A B-tree whose nodes are 256-byte REU pages, so visiting a node is one
+/// page-sized DMA. Insertion and removal work top-down in a single pass,
+/// splitting or refilling nodes on the way, so no node has to be visited
+/// twice. Keys and values are returned as copies.
+///
+/// To construct use `new`
+pub struct REUBTreeMap<K, V> {
+    segments: Vec<ReuChunk>,
+    free_nodes: Vec<u16>,
+    node_count: u16,
+    root: u16,
+    len: u32,
+    _marker: PhantomData<(K, V)>,
+}
+
+impl<K: ReuSafe + Ord, V: ReuSafe> Default for REUBTreeMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: ReuSafe + Ord, V: ReuSafe> REUBTreeMap<K, V> {
+    pub fn new() -> Self {
+        assert!(Node::<K, V>::MAX >= 3, "btree entries too big");
+        let mut map = REUBTreeMap {
+            segments: Vec::new(),
+            free_nodes: Vec::new(),
+            node_count: 0,
+            root: NO_NODE,
+            len: 0,
+            _marker: PhantomData,
+        };
+        let root = map.new_node(true);
+        map.root = root.index;
+        map.store(&root);
+        map
+    }
+
+    /// Number of entries in the map
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Get a copy of the value stored under `key`
+    pub fn get(&self, key: &K) -> Option<V> {
+        let mut node = self.load(self.root);
+        loop {
+            match node.search(key) {
+                Ok(i) => return Some(node.value(i)),
+                Err(_) if node.is_leaf() => return None,
+                Err(i) => node = self.load(node.child(i)),
+            }
+        }
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Entry with the smallest key
+    pub fn first_key_value(&self) -> Option<(K, V)> {
+        if self.len == 0 {
+            return None;
+        }
+        Some(self.min_entry(&self.load(self.root)))
+    }
+
+    /// Entry with the largest key
+    pub fn last_key_value(&self) -> Option<(K, V)> {
+        if self.len == 0 {
+            return None;
+        }
+        Some(self.max_entry(&self.load(self.root)))
+    }
+
+    /// Insert `value` under `key`, returning the value it replaced
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let mut node = self.load(self.root);
+        if node.len() == Node::<K, V>::MAX {
+            // grow a level
+            let mut root = self.new_node(false);
+            root.set_child(0, node.index);
+            self.root = root.index;
+            self.split_child(&mut root, 0, &mut node);
+            node = root;
+        }
+
+        loop {
+            match node.search(&key) {
+                Ok(i) => {
+                    let old = node.value(i);
+                    node.set_value(i, value);
+                    self.store(&node);
+                    return Some(old);
+                }
+                Err(i) if node.is_leaf() => {
+                    node.insert_at(i, key, value);
+                    self.store(&node);
+                    self.len += 1;
+                    return None;
+                }
+                Err(i) => {
+                    let mut child = self.load(node.child(i));
+                    if child.len() == Node::<K, V>::MAX {
+                        let sibling = self.split_child(&mut node, i, &mut child);
+                        match key.cmp(&node.key(i)) {
+                            Ordering::Greater => child = sibling,
+                            Ordering::Less => {}
+                            Ordering::Equal => {
+                                let old = node.value(i);
+                                node.set_value(i, value);
+                                self.store(&node);
+                                return Some(old);
+                            }
+                        }
+                    }
+                    node = child;
+                }
+            }
+        }
+    }
+
+    /// Remove `key` from the map, returning its value
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let mut key = *key;
+        let mut removed = None;
+        let mut node = self.load(self.root);
+        loop {
+            match node.search(&key) {
+                Ok(i) if node.is_leaf() => {
+                    let (_, value) = node.remove_at(i);
+                    self.store(&node);
+                    self.len -= 1;
+                    return removed.or(Some(value));
+                }
+                Err(_) if node.is_leaf() => return None,
+                Ok(i) => {
+                    // replace the key with its predecessor or successor and
+                    // go on removing that one from the leaf it lives in
+                    let mut left = self.load(node.child(i));
+                    if left.len() > Node::<K, V>::MIN {
+                        let (k, v) = self.max_entry(&left);
+                        removed = Some(node.value(i));
+                        node.set_key(i, k);
+                        node.set_value(i, v);
+                        self.store(&node);
+                        key = k;
+                        node = left;
+                        continue;
+                    }
+                    let right = self.load(node.child(i + 1));
+                    if right.len() > Node::<K, V>::MIN {
+                        let (k, v) = self.min_entry(&right);
+                        removed = Some(node.value(i));
+                        node.set_key(i, k);
+                        node.set_value(i, v);
+                        self.store(&node);
+                        key = k;
+                        node = right;
+                        continue;
+                    }
+                    self.merge(&mut node, i, &mut left, right);
+                    node = left;
+                }
+                Err(i) => {
+                    let child = self.load(node.child(i));
+                    node = if child.len() == Node::<K, V>::MIN {
+                        self.refill_child(&mut node, i, child)
+                    } else {
+                        child
+                    };
+                }
+            }
+        }
+    }
+
+    /// Obtain iterator over copies of all entries in key order
+    pub fn iter(&self) -> REUBTreeMapRange<K, V> {
+        self.range(..)
+    }
+
+    /// Obtain iterator over copies of entries with keys in `range`, in key order
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> REUBTreeMapRange<K, V> {
+        let mut iter = REUBTreeMapRange {
+            map: self,
+            stack: Vec::new(),
+            node: self.load(self.root),
+            end: match range.end_bound() {
+                Bound::Included(key) => Bound::Included(*key),
+                Bound::Excluded(key) => Bound::Excluded(*key),
+                Bound::Unbounded => Bound::Unbounded,
+            },
+        };
+
+        // walk down to the first entry in range, stack holds what comes after it
+        loop {
+            let node = &iter.node;
+            let step = match range.start_bound() {
+                Bound::Unbounded => {
+                    iter.stack.push((node.index, 0));
+                    break;
+                }
+                Bound::Included(key) => match node.search(key) {
+                    Ok(i) => {
+                        iter.stack.push((node.index, 2 * i as u16 + 1));
+                        break;
+                    }
+                    Err(i) => i,
+                },
+                Bound::Excluded(key) => match node.search(key) {
+                    Ok(i) => {
+                        iter.stack.push((node.index, 2 * (i as u16 + 1)));
+                        break;
+                    }
+                    Err(i) => i,
+                },
+            };
+            iter.stack.push((node.index, 2 * step as u16 + 1));
+            if node.is_leaf() {
+                break;
+            }
+            iter.node = self.load(node.child(step));
+        }
+        iter
+    }
+
+    /// Split full `child` under `parent` at `i`, the median moves up into `parent`
+    ///
+    /// Returns the new right sibling, all three nodes are stored.
+    fn split_child(
+        &mut self,
+        parent: &mut Node<K, V>,
+        i: usize,
+        child: &mut Node<K, V>,
+    ) -> Node<K, V> {
+        let t = Node::<K, V>::MIN + 1;
+        let mut sibling = self.new_node(child.is_leaf());
+        for j in 0..t - 1 {
+            sibling.set_key(j, child.key(t + j));
+            sibling.set_value(j, child.value(t + j));
+        }
+        if !child.is_leaf() {
+            for j in 0..t {
+                sibling.set_child(j, child.child(t + j));
+            }
+        }
+        sibling.set_len(t - 1);
+        child.set_len(t - 1);
+
+        parent.insert_at(i, child.key(t - 1), child.value(t - 1));
+        parent.insert_child(i + 1, sibling.index);
+
+        self.store(child);
+        self.store(&sibling);
+        self.store(parent);
+        sibling
+    }
+
+    /// Move entry `i` of `parent` and all of `right` into `left`
+    ///
+    /// An emptied root is freed and `left` becomes the root.
+    fn merge(
+        &mut self,
+        parent: &mut Node<K, V>,
+        i: usize,
+        left: &mut Node<K, V>,
+        right: Node<K, V>,
+    ) {
+        let (key, value) = parent.remove_at(i);
+        parent.remove_child(i + 1);
+
+        let len = left.len();
+        left.set_key(len, key);
+        left.set_value(len, value);
+        for j in 0..right.len() {
+            left.set_key(len + 1 + j, right.key(j));
+            left.set_value(len + 1 + j, right.value(j));
+        }
+        if !left.is_leaf() {
+            for j in 0..=right.len() {
+                left.set_child(len + 1 + j, right.child(j));
+            }
+        }
+        left.set_len(len + 1 + right.len());
+
+        self.free_nodes.push(right.index);
+        self.store(left);
+        if parent.len() == 0 && parent.index == self.root {
+            self.free_nodes.push(parent.index);
+            self.root = left.index;
+        } else {
+            self.store(parent);
+        }
+    }
+
+    /// Give minimal `child` at `i` an extra entry from a sibling, or merge
+    /// it with one, so removal can descend into it
+    fn refill_child(
+        &mut self,
+        parent: &mut Node<K, V>,
+        i: usize,
+        mut child: Node<K, V>,
+    ) -> Node<K, V> {
+        let left = if i > 0 {
+            let mut left = self.load(parent.child(i - 1));
+            if left.len() > Node::<K, V>::MIN {
+                // rotate right through the parent
+                child.insert_at(0, parent.key(i - 1), parent.value(i - 1));
+                if !child.is_leaf() {
+                    child.insert_child(0, left.child(left.len()));
+                }
+                let (key, value) = left.remove_at(left.len() - 1);
+                parent.set_key(i - 1, key);
+                parent.set_value(i - 1, value);
+                self.store(&left);
+                self.store(&child);
+                self.store(parent);
+                return child;
+            }
+            Some(left)
+        } else {
+            None
+        };
+
+        if i < parent.len() {
+            let mut right = self.load(parent.child(i + 1));
+            if right.len() > Node::<K, V>::MIN {
+                // rotate left through the parent
+                let len = child.len();
+                child.insert_at(len, parent.key(i), parent.value(i));
+                if !child.is_leaf() {
+                    child.set_child(len + 1, right.child(0));
+                }
+                let (key, value) = right.remove_at(0);
+                if !right.is_leaf() {
+                    right.remove_child(0);
+                }
+                parent.set_key(i, key);
+                parent.set_value(i, value);
+                self.store(&right);
+                self.store(&child);
+                self.store(parent);
+                return child;
+            }
+            self.merge(parent, i, &mut child, right);
+            child
+        } else {
+            let mut left = left.unwrap();
+            self.merge(parent, i - 1, &mut left, child);
+            left
+        }
+    }
+
+    /// Largest entry in the subtree under `node`
+    fn max_entry(&self, node: &Node<K, V>) -> (K, V) {
+        if node.is_leaf() {
+            let last = node.len() - 1;
+            (node.key(last), node.value(last))
+        } else {
+            self.max_entry(&self.load(node.child(node.len())))
+        }
+    }
+
+    /// Smallest entry in the subtree under `node`
+    fn min_entry(&self, node: &Node<K, V>) -> (K, V) {
+        if node.is_leaf() {
+            (node.key(0), node.value(0))
+        } else {
+            self.min_entry(&self.load(node.child(0)))
+        }
+    }
+
+    fn new_node(&mut self, leaf: bool) -> Node<K, V> {
+        let index = match self.free_nodes.pop() {
+            Some(index) => index,
+            None => {
+                if self.node_count == self.segments.len() as u16 * SEGMENT_NODES {
+                    assert!(self.node_count < NO_NODE - SEGMENT_NODES, "btree too big");
+                    let size = SEGMENT_NODES as u32 * NODE_SIZE as u32;
+                    self.segments.push(expansion_memory::memory().alloc(size));
+                }
+                self.node_count += 1;
+                self.node_count - 1
+            }
+        };
+        let mut node = Node {
+            index,
+            page: [0; NODE_SIZE],
+            _marker: PhantomData,
+        };
+        node.page[1] = leaf as u8;
+        node
+    }
+
+    fn load(&self, index: u16) -> Node<K, V> {
+        let mut node = Node {
+            index,
+            page: [0; NODE_SIZE],
+            _marker: PhantomData,
+        };
+        self.segment(index).pull_at(
+            expansion_memory::memory(),
+            node.page.as_mut_ptr() as usize,
+            Self::node_offset(index),
+            NODE_SIZE,
+        );
+        node
+    }
+
+    fn store(&self, node: &Node<K, V>) {
+        self.segment(node.index).push_at(
+            expansion_memory::memory(),
+            node.page.as_ptr() as usize,
+            Self::node_offset(node.index),
+            NODE_SIZE,
+        );
+    }
+
+    fn segment(&self, index: u16) -> &ReuChunk {
+        &self.segments[(index / SEGMENT_NODES) as usize]
+    }
+
+    fn node_offset(index: u16) -> u32 {
+        (index % SEGMENT_NODES) as u32 * NODE_SIZE as u32
+    }
+}
+
+/// In-order iterator over a `REUBTreeMap`
+///
+/// Keeps one node in RAM and a stack of the nodes above it, each with the
+/// step it's at: even step `2 * i` descends into child `i`, odd step
+/// `2 * i + 1` yields entry `i`.
+pub struct REUBTreeMapRange<'a, K, V> {
+    map: &'a REUBTreeMap<K, V>,
+    stack: Vec<(u16, u16)>,
+    node: Node<K, V>,
+    end: Bound<K>,
+}
+
+impl<'a, K: ReuSafe + Ord, V: ReuSafe> Iterator for REUBTreeMapRange<'a, K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(top) = self.stack.last_mut() {
+            let (index, step) = *top;
+            if self.node.index != index {
+                self.node = self.map.load(index);
+            }
+            if step as usize > 2 * self.node.len() {
+                self.stack.pop();
+                continue;
+            }
+            top.1 += 1;
+
+            let i = (step / 2) as usize;
+            if step % 2 == 1 {
+                let key = self.node.key(i);
+                let past_end = match &self.end {
+                    Bound::Included(end) => key > *end,
+                    Bound::Excluded(end) => key >= *end,
+                    Bound::Unbounded => false,
+                };
+                if past_end {
+                    self.stack.clear();
+                    return None;
+                }
+                return Some((key, self.node.value(i)));
+            } else if !self.node.is_leaf() {
+                let child = self.node.child(i);
+                self.stack.push((child, 0));
+            }
+        }
+        None
+    }
+}
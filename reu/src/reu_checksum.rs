@@ -0,0 +1,63 @@
+use crate::checksum::{Crc16, Crc32, Sum16};
+use crate::expansion_memory;
+use crate::reu_allocator::ReuChunk;
+
+/// Chunk contents are checksummed through a RAM buffer of this size
+const BUFFER_SIZE: usize = 64;
+/// `seal` keeps the CRC-32 in the last bytes of the chunk
+const SEAL_SIZE: u32 = 4;
+
+impl ReuChunk {
+    /// CRC-16 of `length` bytes starting `offset` bytes into the chunk
+    pub fn crc16(&self, offset: u32, length: u32) -> u16 {
+        let mut crc = Crc16::new();
+        self.stream(offset, length, |bytes| crc.update(bytes));
+        crc.finish()
+    }
+
+    /// CRC-32 of `length` bytes starting `offset` bytes into the chunk
+    pub fn crc32(&self, offset: u32, length: u32) -> u32 {
+        let mut crc = Crc32::new();
+        self.stream(offset, length, |bytes| crc.update(bytes));
+        crc.finish()
+    }
+
+    /// Additive checksum of `length` bytes starting `offset` bytes into the chunk
+    pub fn sum16(&self, offset: u32, length: u32) -> u16 {
+        let mut sum = Sum16::new();
+        self.stream(offset, length, |bytes| sum.update(bytes));
+        sum.finish()
+    }
+
+    /// Store the CRC-32 of the chunk in its last 4 bytes, for `check`
+    ///
+    /// The data itself has to leave those 4 bytes free.
+    pub fn seal(&self) {
+        let data_len = self.sealed_len();
+        self.write(data_len, &self.crc32(0, data_len));
+    }
+
+    /// Whether the chunk still holds what it held when it was sealed
+    pub fn check(&self) -> bool {
+        let data_len = self.sealed_len();
+        self.read::<u32>(data_len) == self.crc32(0, data_len)
+    }
+
+    fn sealed_len(&self) -> u32 {
+        assert!(self.len() >= SEAL_SIZE, "reu chunk too small to seal");
+        self.len() - SEAL_SIZE
+    }
+
+    /// Hand the given range to `f` in pieces of at most `BUFFER_SIZE` bytes
+    fn stream(&self, mut offset: u32, mut length: u32, mut f: impl FnMut(&[u8])) {
+        let reu = expansion_memory::memory();
+        let mut buffer = [0u8; BUFFER_SIZE];
+        while length > 0 {
+            let count = length.min(BUFFER_SIZE as u32) as usize;
+            self.pull_at(reu, buffer.as_mut_ptr() as usize, offset, count);
+            f(&buffer[..count]);
+            offset += count as u32;
+            length -= count as u32;
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use crate::expansion_memory::ExpansionMemory;
+use crate::lz;
+use crate::reu_allocator::ReuChunk;
+use core::slice;
+
+/// Compressed data goes through a RAM buffer of this size
+const BUFFER_SIZE: usize = 64;
+
+impl ReuChunk {
+    /// Compress `length` bytes of C64 RAM into the chunk, returns the compressed size
+    ///
+    /// The data is compressed with `lz`, so assets can also be packed on the
+    /// host with `lz::compress` and loaded into a chunk as they are.
+    pub fn push_compressed(
+        &self,
+        memory: &dyn ExpansionMemory,
+        c64_start: usize,
+        length: usize,
+    ) -> u32 {
+        let input = unsafe { slice::from_raw_parts(c64_start as *const u8, length) };
+        let mut buffer = [0u8; BUFFER_SIZE];
+        let mut buffered = 0;
+        let mut written = 0;
+
+        lz::compress_with(input, |bytes| {
+            for &byte in bytes {
+                if buffered == BUFFER_SIZE {
+                    self.push_buffer(memory, &buffer, written);
+                    written += BUFFER_SIZE as u32;
+                    buffered = 0;
+                }
+                buffer[buffered] = byte;
+                buffered += 1;
+            }
+        });
+        self.push_buffer(memory, &buffer[..buffered], written);
+        written + buffered as u32
+    }
+
+    /// Decompress the chunk into C64 RAM, returns the decompressed size
+    ///
+    /// `length` is the room there is at `c64_start`, it's an error for the
+    /// data to decompress into more.
+    pub fn pull_decompressed(
+        &self,
+        memory: &dyn ExpansionMemory,
+        c64_start: usize,
+        length: usize,
+    ) -> usize {
+        let output = unsafe { slice::from_raw_parts_mut(c64_start as *mut u8, length) };
+        let mut buffer = [0u8; BUFFER_SIZE];
+        let mut buffered = 0;
+        let mut next = 0;
+        let mut read = 0;
+
+        lz::decompress_with(
+            || {
+                if next == buffered {
+                    let count = (self.len() - read).min(BUFFER_SIZE as u32);
+                    assert!(count > 0, "bad compressed data");
+                    self.pull_at(memory, buffer.as_mut_ptr() as usize, read, count as usize);
+                    read += count;
+                    buffered = count as usize;
+                    next = 0;
+                }
+                next += 1;
+                buffer[next - 1]
+            },
+            output,
+        )
+    }
+
+    fn push_buffer(&self, memory: &dyn ExpansionMemory, buffer: &[u8], offset: u32) {
+        assert!(
+            offset + buffer.len() as u32 <= self.len(),
+            "compressed data too large"
+        );
+        self.push_at(memory, buffer.as_ptr() as usize, offset, buffer.len());
+    }
+}
@@ -0,0 +1,222 @@
+extern crate alloc;
+
+use crate::expansion_memory;
+use crate::ram_expansion_unit::RamExpanstionUnit;
+use alloc::vec;
+use alloc::vec::Vec;
+use ufmt_stdio::println;
+
+const BANK_SIZE: u32 = 0x10000;
+const PAGE: usize = 256;
+
+/// Results of `RamExpanstionUnit::self_test`
+pub struct DiagnosticReport {
+    /// 64KB banks found, from where addresses wrap around
+    pub banks: u16,
+    /// Data bits that didn't read back as written, 0 when all work
+    pub data_lines: u8,
+    /// REU address bits that don't select their own memory, 0 when all work
+    pub address_lines: u32,
+    /// RAM pushed to REU and pulled back matches
+    pub push_pull: bool,
+    /// SWAP exchanges RAM and REU contents
+    pub swap: bool,
+    /// VERIFY reports both equal and different data
+    pub verify: bool,
+    /// Banks where a walking-bit pattern didn't verify
+    pub walking_bit_failures: u16,
+    /// Transfers carry on into the next bank and wrap from the end of memory to 0
+    pub wrap_around: bool,
+}
+
+impl DiagnosticReport {
+    /// Whether every test passed
+    pub fn passed(&self) -> bool {
+        self.data_lines == 0
+            && self.address_lines == 0
+            && self.push_pull
+            && self.swap
+            && self.verify
+            && self.walking_bit_failures == 0
+            && self.wrap_around
+    }
+
+    /// Print a summary on screen
+    pub fn print(&self) {
+        println!("reu size: {}kb", self.banks as u32 * 64);
+        println!("data lines: {}", result(self.data_lines == 0));
+        println!("address lines: {}", result(self.address_lines == 0));
+        println!("push/pull: {}", result(self.push_pull));
+        println!("swap: {}", result(self.swap));
+        println!("verify: {}", result(self.verify));
+        if self.walking_bit_failures == 0 {
+            println!("walking bit: ok");
+        } else {
+            println!("walking bit: {} banks failed", self.walking_bit_failures);
+        }
+        println!("wrap-around: {}", result(self.wrap_around));
+        println!("reu {}", if self.passed() { "ok" } else { "failed" });
+    }
+}
+
+fn result(ok: bool) -> &'static str {
+    if ok {
+        "ok"
+    } else {
+        "failed"
+    }
+}
+
+impl RamExpanstionUnit {
+    /// Test the REU hardware
+    ///
+    /// Checks data and address lines, the transfer commands and every
+    /// detected bank, which takes a few seconds with 16MB. This overwrites
+    /// REU contents all over, including the allocator's, so it has to be
+    /// run before `init_allocator`, with the REU as the selected expansion
+    /// memory.
+    pub fn self_test(&self) -> DiagnosticReport {
+        assert!(expansion_memory::reu_selected(), "self-test needs the reu");
+        let banks = self.probe_banks() as u16;
+        // with bank 0 failing nothing works, but the tests still say what
+        let tested = banks.max(1);
+        let mut report = DiagnosticReport {
+            banks,
+            data_lines: self.test_data_lines(),
+            address_lines: self.test_address_lines(tested),
+            push_pull: false,
+            swap: false,
+            verify: false,
+            walking_bit_failures: 0,
+            wrap_around: false,
+        };
+        let mut pattern = vec![0u8; PAGE];
+        let mut buffer = vec![0u8; PAGE];
+        for (i, byte) in pattern.iter_mut().enumerate() {
+            *byte = i as u8 ^ 0xA5;
+        }
+        report.push_pull = self.test_push_pull(&pattern, &mut buffer);
+        report.swap = self.test_swap(&pattern, &mut buffer);
+        report.verify = self.test_verify(&mut buffer);
+        report.walking_bit_failures = self.test_walking_bit(tested, &mut buffer);
+        report.wrap_around = self.test_wrap_around(tested, &pattern, &mut buffer);
+        report
+    }
+
+    fn test_data_lines(&self) -> u8 {
+        let mut failed = 0;
+        let mut patterns: Vec<u8> = vec![0x00, 0xFF, 0x55, 0xAA];
+        for bit in 0..8 {
+            patterns.push(1 << bit);
+            patterns.push(!(1 << bit));
+        }
+        for value in patterns {
+            self.put(0, &[value]);
+            failed |= self.get_byte(0) ^ value;
+        }
+        failed
+    }
+
+    /// Each power-of-two address gets its own value, a broken line makes
+    /// two of them the same memory
+    fn test_address_lines(&self, banks: u16) -> u32 {
+        let lines = 16 + (banks as u32).trailing_zeros();
+        let mut failed = 0;
+        self.put(0, &[0]);
+        for line in 0..lines {
+            self.put(1 << line, &[line as u8 + 1]);
+        }
+        let at_zero = self.get_byte(0);
+        if at_zero != 0 {
+            failed |= 1 << (at_zero - 1).min(31);
+        }
+        for line in 0..lines {
+            if self.get_byte(1 << line) != line as u8 + 1 {
+                failed |= 1 << line;
+            }
+        }
+        failed
+    }
+
+    fn test_push_pull(&self, pattern: &[u8], buffer: &mut [u8]) -> bool {
+        self.put(0, pattern);
+        self.get(0, buffer);
+        buffer == pattern
+    }
+
+    fn test_swap(&self, pattern: &[u8], buffer: &mut [u8]) -> bool {
+        self.put(0, pattern);
+        for (byte, value) in buffer.iter_mut().zip(pattern) {
+            *byte = !value;
+        }
+        self.set_range(buffer.as_ptr() as usize, 0, buffer.len());
+        self.swap();
+        if buffer != pattern {
+            return false;
+        }
+        self.get(0, buffer);
+        buffer
+            .iter()
+            .zip(pattern)
+            .all(|(byte, value)| *byte == !value)
+    }
+
+    fn test_verify(&self, buffer: &mut [u8]) -> bool {
+        self.put(0, buffer);
+        self.set_range(buffer.as_ptr() as usize, 0, buffer.len());
+        let equal = self.verify();
+        buffer[PAGE / 2] ^= 0x10;
+        self.set_range(buffer.as_ptr() as usize, 0, buffer.len());
+        let different = !self.verify();
+        equal && different
+    }
+
+    /// A page of each bank, a different one per bank, holds every rotation
+    /// of a single set bit and of a single clear bit
+    fn test_walking_bit(&self, banks: u16, buffer: &mut [u8]) -> u16 {
+        let mut failed = vec![false; banks as usize];
+        for shift in 0..16 {
+            for (i, byte) in buffer.iter_mut().enumerate() {
+                let bit = 1u8 << ((i + shift) % 8);
+                *byte = if shift < 8 { bit } else { !bit };
+            }
+            for (bank, failed) in failed.iter_mut().enumerate() {
+                let address = bank as u32 * BANK_SIZE + (bank as u32 & 0xFF) * PAGE as u32;
+                self.put(address, buffer);
+                self.set_range(buffer.as_ptr() as usize, address, buffer.len());
+                *failed |= !self.verify();
+            }
+        }
+        failed.iter().filter(|failed| **failed).count() as u16
+    }
+
+    /// A page written across the end of a bank and across the end of memory
+    /// continues at the start of the next bank and at 0
+    fn test_wrap_around(&self, banks: u16, pattern: &[u8], buffer: &mut [u8]) -> bool {
+        let half = PAGE / 2;
+        let end = banks as u32 * BANK_SIZE;
+        let mut ok = true;
+        for (start, wrapped) in [(BANK_SIZE - half as u32, BANK_SIZE), (end - half as u32, 0)] {
+            self.put(start, pattern);
+            self.get(wrapped, &mut buffer[..half]);
+            ok &= buffer[..half] == pattern[half..];
+        }
+        ok
+    }
+
+    fn put(&self, address: u32, bytes: &[u8]) {
+        self.set_range(bytes.as_ptr() as usize, address, bytes.len());
+        self.push();
+    }
+
+    fn get(&self, address: u32, bytes: &mut [u8]) {
+        self.set_range(bytes.as_mut_ptr() as usize, address, bytes.len());
+        self.pull();
+    }
+
+    fn get_byte(&self, address: u32) -> u8 {
+        let mut byte = [0];
+        self.get(address, &mut byte);
+        byte[0]
+    }
+}
@@ -0,0 +1,254 @@
+extern crate alloc;
+
+use crate::expansion_memory;
+use crate::reu_allocator::ReuChunk;
+use crate::reu_safe::ReuSafe;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::mem::size_of;
+use core::ops::{Index, IndexMut};
+
+/// A two-dimensional array of T stored in REU memory, row by row
+///
+/// Meant for tile maps bigger than RAM. Single cells are transferred one
+/// at a time, rectangles are moved with one DMA per row, and `window`
+/// pulls a rectangle into RAM for random access, pushing it back when it
+/// was modified.
+///
+/// To construct use `new`
+pub struct REUGrid<T: ReuSafe> {
+    chunk: ReuChunk,
+    width: u16,
+    height: u16,
+    _marker: PhantomData<T>,
+}
+
+impl<T: ReuSafe> REUGrid<T> {
+    /// Allocate a `width` x `height` grid in REU with every cell set to `value`
+    pub fn new(width: u16, height: u16, value: T) -> Self {
+        let size = width as u32 * height as u32 * size_of::<T>() as u32;
+        let mut grid = REUGrid {
+            chunk: expansion_memory::memory().alloc(size),
+            width,
+            height,
+            _marker: PhantomData,
+        };
+        grid.fill_rect(0, 0, width, height, value);
+        grid
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    /// Copy of the cell at `x`, `y`
+    pub fn get(&self, x: u16, y: u16) -> T {
+        self.check_rect(x, y, 1, 1);
+        self.chunk.read(self.offset(x, y))
+    }
+
+    /// Overwrite the cell at `x`, `y`
+    pub fn set(&mut self, x: u16, y: u16, value: T) {
+        self.check_rect(x, y, 1, 1);
+        self.chunk.write(self.offset(x, y), &value);
+    }
+
+    /// Copy row `y` into `dst`, which has to hold `width` cells
+    pub fn read_row(&self, y: u16, dst: &mut [T]) {
+        self.read_rect(0, y, self.width, 1, dst);
+    }
+
+    /// Overwrite row `y` with `width` cells from `src`
+    pub fn write_row(&mut self, y: u16, src: &[T]) {
+        self.write_rect(0, y, self.width, 1, src);
+    }
+
+    /// Copy column `x` into `dst`, which has to hold `height` cells
+    ///
+    /// Cells of a column aren't adjacent in REU, so this is one DMA per cell.
+    pub fn read_column(&self, x: u16, dst: &mut [T]) {
+        self.read_rect(x, 0, 1, self.height, dst);
+    }
+
+    /// Overwrite column `x` with `height` cells from `src`
+    pub fn write_column(&mut self, x: u16, src: &[T]) {
+        self.write_rect(x, 0, 1, self.height, src);
+    }
+
+    /// Copy a `width` x `height` rectangle at `x`, `y` into `dst` row by row
+    pub fn read_rect(&self, x: u16, y: u16, width: u16, height: u16, dst: &mut [T]) {
+        self.check_rect(x, y, width, height);
+        assert!(
+            dst.len() == width as usize * height as usize,
+            "rect buffer {}/{}x{}",
+            dst.len(),
+            width,
+            height
+        );
+        self.pull_rect(x, y, width, height, dst.as_mut_ptr());
+    }
+
+    /// Overwrite a `width` x `height` rectangle at `x`, `y` with `src` row by row
+    pub fn write_rect(&mut self, x: u16, y: u16, width: u16, height: u16, src: &[T]) {
+        self.check_rect(x, y, width, height);
+        assert!(
+            src.len() == width as usize * height as usize,
+            "rect buffer {}/{}x{}",
+            src.len(),
+            width,
+            height
+        );
+        for row in 0..height {
+            let at = row as usize * width as usize;
+            self.chunk.push_at(
+                expansion_memory::memory(),
+                src[at..].as_ptr() as usize,
+                self.offset(x, y + row),
+                width as usize * size_of::<T>(),
+            );
+        }
+    }
+
+    /// Set every cell of a rectangle to `value`
+    pub fn fill_rect(&mut self, x: u16, y: u16, width: u16, height: u16, value: T) {
+        self.check_rect(x, y, width, height);
+        let row = alloc::vec![value; width as usize];
+        for y in y..y + height {
+            self.chunk.push_at(
+                expansion_memory::memory(),
+                row.as_ptr() as usize,
+                self.offset(x, y),
+                row.len() * size_of::<T>(),
+            );
+        }
+    }
+
+    /// Pull a rectangle into RAM, see `REUGridWindow`
+    pub fn window(&mut self, x: u16, y: u16, width: u16, height: u16) -> REUGridWindow<T> {
+        self.check_rect(x, y, width, height);
+        let count = width as usize * height as usize;
+        let mut cells = Vec::with_capacity(count);
+        self.pull_rect(x, y, width, height, cells.as_mut_ptr());
+        unsafe {
+            cells.set_len(count);
+        }
+        REUGridWindow {
+            grid: self,
+            cells,
+            x,
+            y,
+            width,
+            height,
+            dirty: false,
+        }
+    }
+
+    /// Pull a rectangle row by row into RAM at `dst`
+    fn pull_rect(&self, x: u16, y: u16, width: u16, height: u16, dst: *mut T) {
+        for row in 0..height {
+            let at = row as usize * width as usize;
+            self.chunk.pull_at(
+                expansion_memory::memory(),
+                dst.wrapping_add(at) as usize,
+                self.offset(x, y + row),
+                width as usize * size_of::<T>(),
+            );
+        }
+    }
+
+    fn offset(&self, x: u16, y: u16) -> u32 {
+        (y as u32 * self.width as u32 + x as u32) * size_of::<T>() as u32
+    }
+
+    fn check_rect(&self, x: u16, y: u16, width: u16, height: u16) {
+        assert!(
+            x as u32 + width as u32 <= self.width as u32
+                && y as u32 + height as u32 <= self.height as u32,
+            "grid rect {},{}+{}x{}/{}x{}",
+            x,
+            y,
+            width,
+            height,
+            self.width,
+            self.height
+        );
+    }
+}
+
+/// A rectangle of an `REUGrid` pulled into RAM
+///
+/// Cells are indexed with `(x, y)` relative to the rectangle. If any cell
+/// was mutably indexed the rectangle is pushed back on drop, one DMA per row.
+pub struct REUGridWindow<'a, T: ReuSafe> {
+    grid: &'a mut REUGrid<T>,
+    cells: Vec<T>,
+    x: u16,
+    y: u16,
+    width: u16,
+    height: u16,
+    dirty: bool,
+}
+
+impl<'a, T: ReuSafe> REUGridWindow<'a, T> {
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    /// Cells of row `y` of the window
+    pub fn row(&self, y: u16) -> &[T] {
+        let at = y as usize * self.width as usize;
+        &self.cells[at..at + self.width as usize]
+    }
+
+    /// Mutable cells of row `y` of the window
+    pub fn row_mut(&mut self, y: u16) -> &mut [T] {
+        self.dirty = true;
+        let at = y as usize * self.width as usize;
+        &mut self.cells[at..at + self.width as usize]
+    }
+
+    fn cell(&self, (x, y): (u16, u16)) -> usize {
+        assert!(
+            x < self.width && y < self.height,
+            "window cell {},{}/{}x{}",
+            x,
+            y,
+            self.width,
+            self.height
+        );
+        y as usize * self.width as usize + x as usize
+    }
+}
+
+impl<'a, T: ReuSafe> Index<(u16, u16)> for REUGridWindow<'a, T> {
+    type Output = T;
+
+    fn index(&self, position: (u16, u16)) -> &T {
+        &self.cells[self.cell(position)]
+    }
+}
+
+impl<'a, T: ReuSafe> IndexMut<(u16, u16)> for REUGridWindow<'a, T> {
+    fn index_mut(&mut self, position: (u16, u16)) -> &mut T {
+        self.dirty = true;
+        let cell = self.cell(position);
+        &mut self.cells[cell]
+    }
+}
+
+impl<'a, T: ReuSafe> Drop for REUGridWindow<'a, T> {
+    fn drop(&mut self) {
+        if self.dirty {
+            self.grid
+                .write_rect(self.x, self.y, self.width, self.height, &self.cells);
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use crate::reu_array::{REUArray, REUArrayIter, WindowAlignment};
+use crate::reu_safe::ReuSafe;
+use core::hash::{Hash, Hasher};
+use core::mem::{size_of, MaybeUninit};
+
+const EMPTY: u8 = 0;
+const FULL: u8 = 1;
+const DELETED: u8 = 2;
+
+/// Size of one bucket page, the same as the REU allocation unit
+const PAGE_SIZE: usize = 256;
+
+#[derive(Clone, Copy, crate::ReuSafe)]
+struct Slot<K: ReuSafe, V: ReuSafe> {
+    state: u8,
+    key: MaybeUninit<K>,
+    value: MaybeUninit<V>,
+}
+
+impl<K: ReuSafe, V: ReuSafe> Slot<K, V> {
+    fn empty() -> Self {
+        Slot {
+            state: EMPTY,
+            key: MaybeUninit::uninit(),
+            value: MaybeUninit::uninit(),
+        }
+    }
+
+    fn full(key: K, value: V) -> Self {
+        Slot {
+            state: FULL,
+            key: MaybeUninit::new(key),
+            value: MaybeUninit::new(value),
+        }
+    }
+}
+
+/// 32-bit FNV-1a, cheap enough for the 6502
+struct FnvHasher(u32);
+
+impl Hasher for FnvHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u32).wrapping_mul(0x0100_0193);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0 as u64
+    }
+}
+
+/// A hash map stored in REU memory
+///
+/// Open addressing with linear probing over an `REUArray` of slots. The
+/// array window is one aligned page of slots, so a probe sequence usually
+/// costs a single page DMA. Once it's 3/4 full (counting removed slots)
+/// the table is rehashed, at the same size if less than half of the slots
+/// hold entries, or else doubled.
+///
+/// To construct use `with_capacity`
+pub struct REUHashMap<K: ReuSafe, V: ReuSafe> {
+    slots: REUArray<Slot<K, V>>,
+    len: u32,
+    used: u32, // full and deleted slots, decides when to rehash
+}
+
+impl<K: ReuSafe + Hash + Eq, V: ReuSafe> REUHashMap<K, V> {
+    /// Create a map that holds at least `capacity` entries before it has to grow
+    pub fn with_capacity(capacity: u32) -> Self {
+        let wanted = capacity + capacity / 3 + 1;
+        let mut slot_count = 8;
+        while slot_count < wanted {
+            slot_count *= 2;
+        }
+        REUHashMap {
+            slots: Self::new_slots(slot_count),
+            len: 0,
+            used: 0,
+        }
+    }
+
+    fn new_slots(slot_count: u32) -> REUArray<Slot<K, V>> {
+        let per_page = PAGE_SIZE / size_of::<Slot<K, V>>();
+        let per_page = if per_page == 0 { 1 } else { per_page };
+        let mut slots = REUArray::with_capacity(slot_count, per_page);
+        slots.set_alignment(WindowAlignment::Aligned);
+        slots.resize(slot_count, Slot::empty());
+        slots
+    }
+
+    /// Number of entries the map holds before it has to grow
+    pub fn capacity(&self) -> u32 {
+        self.slots.len() / 4 * 3
+    }
+
+    /// Number of entries in the map
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Insert `value` under `key`, returning the value it replaced
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        if (self.used + 1) * 4 > self.slots.len() * 3 {
+            self.rehash();
+        }
+
+        let mask = self.slots.len() - 1;
+        let mut index = self.hash(&key) & mask;
+        let mut free = None;
+        loop {
+            let slot = &self.slots[index];
+            match slot.state {
+                EMPTY => break,
+                DELETED => {
+                    if free.is_none() {
+                        free = Some(index);
+                    }
+                }
+                _ => {
+                    if unsafe { slot.key.assume_init_ref() } == &key {
+                        let old = unsafe { slot.value.assume_init() };
+                        self.slots[index].value = MaybeUninit::new(value);
+                        return Some(old);
+                    }
+                }
+            }
+            index = (index + 1) & mask;
+        }
+
+        match free {
+            // reuse a removed slot, `used` doesn't change
+            Some(free) => self.slots[free] = Slot::full(key, value),
+            None => {
+                self.slots[index] = Slot::full(key, value);
+                self.used += 1;
+            }
+        }
+        self.len += 1;
+        None
+    }
+
+    /// Get a copy of the value stored under `key`
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.find(key)
+            .map(|index| unsafe { self.slots[index].value.assume_init() })
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.find(key).is_some()
+    }
+
+    /// Remove `key` from the map, returning its value
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let index = self.find(key)?;
+        let slot = &mut self.slots[index];
+        slot.state = DELETED;
+        self.len -= 1;
+        Some(unsafe { slot.value.assume_init() })
+    }
+
+    /// Remove all entries
+    pub fn clear(&mut self) {
+        let slot_count = self.slots.len();
+        self.slots.fill(0..slot_count, Slot::empty());
+        self.len = 0;
+        self.used = 0;
+    }
+
+    /// Obtain iterator over copies of all `(key, value)` pairs, in no particular order
+    pub fn iter(&self) -> REUHashMapIter<K, V> {
+        REUHashMapIter {
+            slots: self.slots.iter(),
+        }
+    }
+
+    fn find(&self, key: &K) -> Option<u32> {
+        let mask = self.slots.len() - 1;
+        let mut index = self.hash(key) & mask;
+        loop {
+            let slot = &self.slots[index];
+            match slot.state {
+                EMPTY => return None,
+                FULL if unsafe { slot.key.assume_init_ref() } == key => return Some(index),
+                _ => {}
+            }
+            index = (index + 1) & mask;
+        }
+    }
+
+    fn hash(&self, key: &K) -> u32 {
+        let mut hasher = FnvHasher(0x811c_9dc5);
+        key.hash(&mut hasher);
+        hasher.finish() as u32
+    }
+
+    /// Reinsert all entries, dropping removed slots
+    ///
+    /// The slot count is doubled unless less than half of the slots are in
+    /// use, then only the removed slots are cleared out. A map with a lot of
+    /// inserts and removes would otherwise keep growing.
+    fn rehash(&mut self) {
+        let slot_count = if self.len * 2 < self.slots.len() {
+            self.slots.len()
+        } else {
+            self.slots.len() * 2
+        };
+        let old = core::mem::replace(&mut self.slots, Self::new_slots(slot_count));
+        let mask = self.slots.len() - 1;
+        for slot in old.iter().filter(|slot| slot.state == FULL) {
+            let mut index = self.hash(unsafe { slot.key.assume_init_ref() }) & mask;
+            while self.slots[index].state != EMPTY {
+                index = (index + 1) & mask;
+            }
+            self.slots[index] = slot;
+        }
+        self.used = self.len;
+    }
+}
+
+pub struct REUHashMapIter<'a, K: ReuSafe, V: ReuSafe> {
+    slots: REUArrayIter<'a, Slot<K, V>>,
+}
+
+impl<'a, K: ReuSafe, V: ReuSafe> Iterator for REUHashMapIter<'a, K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for slot in self.slots.by_ref() {
+            if slot.state == FULL {
+                return Some(unsafe { (slot.key.assume_init(), slot.value.assume_init()) });
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,183 @@
+extern crate alloc;
+
+use crate::expansion_memory;
+use crate::reu_allocator::ReuChunk;
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+/// Marks a block header or a table entry that isn't in use
+const FREE_HANDLE: u16 = 0xFFFF;
+const FREE_ENTRY: u32 = u32::MAX;
+
+/// Stored in REU in front of every block so compaction can walk the heap
+#[derive(Clone, Copy, crate::ReuSafe)]
+struct BlockHeader {
+    handle: u16,
+    len: u16,
+}
+
+const HEADER_SIZE: u32 = size_of::<BlockHeader>() as u32;
+
+/// Reference to a block of a `ReuHeap`, stays valid when the block moves
+#[derive(Clone, Copy, PartialEq, Eq, crate::ReuSafe)]
+pub struct HeapHandle(u16);
+
+/// A compacting heap in a single REU chunk
+///
+/// Blocks are only reached through handles, which index a table in RAM that
+/// holds each block's current offset. That lets `compact` slide all live
+/// blocks down over the holes left by `free` (REU to REU copies through a
+/// small RAM buffer), and `alloc` does it by itself when the heap is too
+/// fragmented to fit a new block.
+///
+/// To construct use `with_capacity`
+pub struct ReuHeap {
+    chunk: ReuChunk,
+    table: Vec<u32>, // offset of each handle's block header
+    top: u32,        // end of the last block
+    used: u32,       // bytes taken by live blocks and their headers
+}
+
+impl ReuHeap {
+    /// Allocate a heap of `size` bytes in REU
+    pub fn with_capacity(size: u32) -> Self {
+        ReuHeap {
+            chunk: expansion_memory::memory().alloc(size),
+            table: Vec::new(),
+            top: 0,
+            used: 0,
+        }
+    }
+
+    /// Bytes available for new blocks (including their headers) after compaction
+    pub fn free_space(&self) -> u32 {
+        self.chunk.len() - self.used
+    }
+
+    /// Allocate a block of `len` bytes, compacting the heap if needed
+    pub fn alloc(&mut self, len: u16) -> HeapHandle {
+        if len == 0 {
+            panic!("reu 0 alloc");
+        }
+        let size = HEADER_SIZE + len as u32;
+        if self.top + size > self.chunk.len() {
+            self.compact();
+            if self.top + size > self.chunk.len() {
+                panic!("out of reu memory");
+            }
+        }
+
+        let handle = match self.table.iter().position(|entry| *entry == FREE_ENTRY) {
+            Some(index) => index,
+            None => {
+                self.table.push(FREE_ENTRY);
+                self.table.len() - 1
+            }
+        };
+        assert!(handle < FREE_HANDLE as usize, "out of reu heap handles");
+
+        self.chunk.write(
+            self.top,
+            &BlockHeader {
+                handle: handle as u16,
+                len,
+            },
+        );
+        self.table[handle] = self.top;
+        self.top += size;
+        self.used += size;
+        HeapHandle(handle as u16)
+    }
+
+    /// Free a block, its handle may be reused by a later `alloc`
+    pub fn free(&mut self, handle: HeapHandle) {
+        let offset = self.offset(handle);
+        let mut header: BlockHeader = self.chunk.read(offset);
+        let size = HEADER_SIZE + header.len as u32;
+        header.handle = FREE_HANDLE;
+        self.chunk.write(offset, &header);
+        self.table[handle.0 as usize] = FREE_ENTRY;
+        self.used -= size;
+        if offset + size == self.top {
+            self.top = offset;
+        }
+    }
+
+    /// Length of a block in bytes
+    pub fn len(&self, handle: HeapHandle) -> u16 {
+        let header: BlockHeader = self.chunk.read(self.offset(handle));
+        header.len
+    }
+
+    /// Current REU address of a block, only valid until the next `alloc` or `compact`
+    pub fn address(&self, handle: HeapHandle) -> u32 {
+        self.chunk.address + self.offset(handle) + HEADER_SIZE
+    }
+
+    /// Store `data` in a block, starting `offset` bytes in
+    pub fn write(&self, handle: HeapHandle, offset: u16, data: &[u8]) {
+        let block = self.block(handle, offset, data.len());
+        self.chunk.push_at(
+            expansion_memory::memory(),
+            data.as_ptr() as usize,
+            block,
+            data.len(),
+        );
+    }
+
+    /// Fill `buffer` from a block, starting `offset` bytes in
+    pub fn read(&self, handle: HeapHandle, offset: u16, buffer: &mut [u8]) {
+        let block = self.block(handle, offset, buffer.len());
+        self.chunk.pull_at(
+            expansion_memory::memory(),
+            buffer.as_mut_ptr() as usize,
+            block,
+            buffer.len(),
+        );
+    }
+
+    /// Slide all live blocks down to the start of the heap, leaving one free area at the top
+    pub fn compact(&mut self) {
+        let reu = expansion_memory::memory();
+        let mut buffer = [0u8; 128];
+        let mut from = 0;
+        let mut to = 0;
+        while from < self.top {
+            let header: BlockHeader = self.chunk.read(from);
+            let size = HEADER_SIZE + header.len as u32;
+            if header.handle != FREE_HANDLE {
+                if from != to {
+                    reu.copy(
+                        self.chunk.address + from,
+                        self.chunk.address + to,
+                        size,
+                        &mut buffer,
+                    );
+                    self.table[header.handle as usize] = to;
+                }
+                to += size;
+            }
+            from += size;
+        }
+        self.top = to;
+    }
+
+    fn offset(&self, handle: HeapHandle) -> u32 {
+        let offset = self.table[handle.0 as usize];
+        assert!(offset != FREE_ENTRY, "freed reu handle");
+        offset
+    }
+
+    /// Offset of `count` bytes at `offset` within a block, checked against its length
+    fn block(&self, handle: HeapHandle, offset: u16, count: usize) -> u32 {
+        let len = self.len(handle);
+        assert!(
+            offset as usize + count <= len as usize,
+            "reu heap range {}+{}/{}",
+            offset,
+            count,
+            len
+        );
+        self.offset(handle) + HEADER_SIZE + offset as u32
+    }
+}
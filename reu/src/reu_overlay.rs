@@ -0,0 +1,134 @@
+extern crate alloc;
+
+use crate::expansion_memory;
+use crate::reu_allocator::ReuChunk;
+use alloc::vec::Vec;
+use core::ptr::addr_of_mut;
+
+static mut OVERLAYS: Option<OverlayManager> = None;
+
+/// Number of an overlay, given out by `OverlayManager::add` from 0 up
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct OverlayId(pub u8);
+
+/// Code segments kept in REU and run from one fixed RAM region
+///
+/// Each overlay is linked to run at the start of the region and is pulled
+/// into it when one of its functions is called through `call_overlay`.
+/// Only one overlay is resident at a time; the manager remembers which one,
+/// so calling into it again costs no transfer.
+///
+/// Overlay code goes into its own section, e.g.
+/// `#[link_section = ".overlay0"]` on each function, together with
+/// `#[inline(never)]`: with `lto` the compiler could otherwise inline the
+/// function into its callers in the main program, where it would take up
+/// the RAM the overlay was meant to save. The linker script gives all
+/// overlay sections the region as run address and puts them one after
+/// another in the program file:
+///
+/// ```text
+/// OVERLAY 0x8000 : AT(__overlays_load) {
+///     .overlay0 { *(.overlay0 .overlay0.*) }
+///     .overlay1 { *(.overlay1 .overlay1.*) }
+/// }
+/// __overlay0_load = LOADADDR(.overlay0);
+/// __overlay0_size = SIZEOF(.overlay0);
+/// __overlay1_load = LOADADDR(.overlay1);
+/// __overlay1_size = SIZEOF(.overlay1);
+/// ```
+///
+/// At startup the segments are copied to REU with `add`, after which the
+/// RAM they were loaded into can be reused. Code outside the overlays,
+/// including closures and generic functions, stays in the main program.
+pub struct OverlayManager {
+    region: usize,
+    size: usize,
+    overlays: Vec<ReuChunk>,
+    resident: Option<OverlayId>,
+}
+
+/// Set up the global overlay manager with `size` bytes of RAM at `region`
+pub fn init_overlays(region: usize, size: usize) {
+    unsafe {
+        *addr_of_mut!(OVERLAYS) = Some(OverlayManager::new(region, size));
+    }
+}
+
+/// Run `f` with the global overlay manager, `init_overlays` must be called first
+pub fn with_overlays<R>(f: impl FnOnce(&mut OverlayManager) -> R) -> R {
+    let overlays = unsafe {
+        (*addr_of_mut!(OVERLAYS))
+            .as_mut()
+            .expect("overlays not initialized")
+    };
+    f(overlays)
+}
+
+/// Run `f` with overlay `id` resident, for calling functions linked into it
+///
+/// The overlay that was resident before is brought back when `f` returns,
+/// so an overlay can call into another one and return to its own code.
+///
+/// This function must stay in the main program's `.text`: it swaps the
+/// overlays in the region and would overwrite itself if it were inlined
+/// into overlay code, hence `#[inline(never)]`.
+#[inline(never)]
+pub fn call_overlay<R>(id: OverlayId, f: impl FnOnce() -> R) -> R {
+    let caller = with_overlays(|overlays| {
+        let caller = overlays.resident;
+        overlays.load(id);
+        caller
+    });
+    let result = f();
+    if let Some(caller) = caller {
+        with_overlays(|overlays| overlays.load(caller));
+    }
+    result
+}
+
+impl OverlayManager {
+    /// Overlays run from `size` bytes of RAM at `region`
+    pub fn new(region: usize, size: usize) -> Self {
+        OverlayManager {
+            region,
+            size,
+            overlays: Vec::new(),
+            resident: None,
+        }
+    }
+
+    /// Copy `len` bytes of overlay code loaded at `start` into REU
+    pub fn add(&mut self, start: usize, len: usize) -> OverlayId {
+        assert!(len > 0, "empty overlay");
+        let chunk = expansion_memory::memory().alloc(len as u32);
+        chunk.push(expansion_memory::memory(), start);
+        self.add_chunk(chunk)
+    }
+
+    /// Use overlay code already stored in `chunk`, e.g. loaded from disk
+    pub fn add_chunk(&mut self, chunk: ReuChunk) -> OverlayId {
+        assert!(chunk.len() as usize <= self.size, "overlay too large");
+        assert!(self.overlays.len() < u8::MAX as usize, "too many overlays");
+        self.overlays.push(chunk);
+        OverlayId(self.overlays.len() as u8 - 1)
+    }
+
+    /// Pull overlay `id` into the region, unless it's there already
+    pub fn load(&mut self, id: OverlayId) {
+        if self.resident != Some(id) {
+            let chunk = self.overlays.get(id.0 as usize).expect("no such overlay");
+            chunk.pull(expansion_memory::memory(), self.region);
+            self.resident = Some(id);
+        }
+    }
+
+    /// Overlay currently in the region
+    pub fn resident(&self) -> Option<OverlayId> {
+        self.resident
+    }
+
+    /// Forget the resident overlay, for when the region was used for something else
+    pub fn invalidate(&mut self) {
+        self.resident = None;
+    }
+}
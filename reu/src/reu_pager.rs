@@ -0,0 +1,198 @@
+extern crate alloc;
+
+use crate::expansion_memory;
+use alloc::vec::Vec;
+use core::ptr::addr_of_mut;
+
+extern "C" {
+    fn malloc(n: usize) -> *mut u8;
+    fn free(ptr: *mut u8);
+}
+
+pub const PAGE_SIZE: usize = 256;
+const NO_PAGE: u32 = u32::MAX;
+
+static mut PAGER: Option<ReuPager> = None;
+
+/// One RAM frame of the page pool
+#[derive(Clone, Copy)]
+struct Frame {
+    page: u32, // REU page (address >> 8) held in the frame, NO_PAGE if none
+    dirty: bool,
+    referenced: bool, // cleared by the clock hand, set on every access
+}
+
+/// Maps 256-byte REU pages into a fixed pool of RAM frames
+///
+/// The frame table works as a fully associative page table, with the last
+/// translation remembered as a one-entry TLB so walking through a structure
+/// usually doesn't even search the table. When all frames are taken the
+/// clock algorithm picks a victim, which is pushed back to REU only if it
+/// was written to.
+///
+/// REU memory reached through the pager shouldn't be accessed in any other
+/// way without a `flush` first.
+/// Frames of a `ReuChunk` are forgotten when the chunk is freed.
+pub struct ReuPager {
+    pool: *mut u8,
+    frames: Vec<Frame>,
+    hand: usize, // clock hand
+    tlb_page: u32,
+    tlb_frame: usize,
+}
+
+/// Set up the global pager used by `ReuPtr` with `frames` RAM pages
+///
+/// Calling it again replaces the pager, the old one writes its modified
+/// frames back and frees its pool first.
+pub fn init_pager(frames: usize) {
+    unsafe {
+        *addr_of_mut!(PAGER) = None;
+        *addr_of_mut!(PAGER) = Some(ReuPager::new(frames));
+    }
+}
+
+/// Forget the global pager's frames of `len` bytes at `address`, without
+/// writing them back, for memory that is being freed
+pub(crate) fn discard_pages(address: u32, len: u32) {
+    if let Some(pager) = unsafe { (*addr_of_mut!(PAGER)).as_mut() } {
+        pager.discard(address, len);
+    }
+}
+
+/// Run `f` with the global pager, `init_pager` must be called first
+pub fn with_pager<R>(f: impl FnOnce(&mut ReuPager) -> R) -> R {
+    let pager = unsafe {
+        (*addr_of_mut!(PAGER))
+            .as_mut()
+            .expect("pager not initialized")
+    };
+    f(pager)
+}
+
+impl ReuPager {
+    /// Create a pager with a pool of `frames` pages in RAM
+    pub fn new(frames: usize) -> Self {
+        assert!(frames > 0, "no pager frames");
+        let pool = unsafe { malloc(frames * PAGE_SIZE) };
+        if pool.is_null() {
+            panic!("out of memory");
+        }
+        ReuPager {
+            pool,
+            frames: alloc::vec![
+                Frame {
+                    page: NO_PAGE,
+                    dirty: false,
+                    referenced: false,
+                };
+                frames
+            ],
+            hand: 0,
+            tlb_page: NO_PAGE,
+            tlb_frame: 0,
+        }
+    }
+
+    /// RAM address of the REU byte at `address`, paging it in if needed
+    ///
+    /// With `write` set the page is written back to REU when evicted.
+    /// The pointer stays valid until another page is mapped.
+    pub fn map(&mut self, address: u32, write: bool) -> *mut u8 {
+        let page = address >> 8;
+        let frame = if self.tlb_page == page {
+            self.tlb_frame
+        } else {
+            let frame = match self.frames.iter().position(|f| f.page == page) {
+                Some(frame) => frame,
+                None => self.page_in(page),
+            };
+            self.tlb_page = page;
+            self.tlb_frame = frame;
+            frame
+        };
+
+        let entry = &mut self.frames[frame];
+        entry.referenced = true;
+        entry.dirty |= write;
+        unsafe {
+            self.frame_ptr(frame)
+                .add(address as usize & (PAGE_SIZE - 1))
+        }
+    }
+
+    /// Write all modified frames back to REU
+    pub fn flush(&mut self) {
+        for frame in 0..self.frames.len() {
+            self.write_back(frame);
+        }
+    }
+
+    /// Write back and forget all frames, for when REU was changed behind the pager's back
+    pub fn invalidate(&mut self) {
+        self.flush();
+        for frame in self.frames.iter_mut() {
+            frame.page = NO_PAGE;
+        }
+        self.tlb_page = NO_PAGE;
+    }
+
+    /// Forget frames holding any of `len` bytes at `address`, changes to them are lost
+    pub fn discard(&mut self, address: u32, len: u32) {
+        let first = address >> 8;
+        let end = (address + len + PAGE_SIZE as u32 - 1) >> 8;
+        for frame in self.frames.iter_mut() {
+            if frame.page >= first && frame.page < end {
+                frame.page = NO_PAGE;
+                frame.dirty = false;
+            }
+        }
+        if self.tlb_page >= first && self.tlb_page < end {
+            self.tlb_page = NO_PAGE;
+        }
+    }
+
+    /// Pick a frame with the clock algorithm and pull `page` into it
+    fn page_in(&mut self, page: u32) -> usize {
+        let frame = loop {
+            let hand = self.hand;
+            self.hand = (self.hand + 1) % self.frames.len();
+            let entry = &mut self.frames[hand];
+            if entry.page == NO_PAGE || !entry.referenced {
+                break hand;
+            }
+            entry.referenced = false;
+        };
+
+        self.write_back(frame);
+        let reu = expansion_memory::memory();
+        reu.read(self.frame_ptr(frame) as usize, page << 8, PAGE_SIZE);
+        self.frames[frame] = Frame {
+            page,
+            dirty: false,
+            referenced: true,
+        };
+        frame
+    }
+
+    fn write_back(&mut self, frame: usize) {
+        let entry = &mut self.frames[frame];
+        if entry.dirty {
+            let page = entry.page;
+            entry.dirty = false;
+            let reu = expansion_memory::memory();
+            reu.write(self.frame_ptr(frame) as usize, page << 8, PAGE_SIZE);
+        }
+    }
+
+    fn frame_ptr(&self, frame: usize) -> *mut u8 {
+        unsafe { self.pool.add(frame * PAGE_SIZE) }
+    }
+}
+
+impl Drop for ReuPager {
+    fn drop(&mut self) {
+        self.flush();
+        unsafe { free(self.pool) };
+    }
+}
@@ -0,0 +1,266 @@
+use crate::expansion_memory::{self, ExpansionMemory};
+use crate::ram_expansion_unit::RamExpanstionUnit;
+use crate::reu_allocator::{ReuChunk, PERSISTENT_HEADER_ADDRESS, PERSISTENT_HEADER_SIZE};
+use core::mem::{size_of, ManuallyDrop, MaybeUninit};
+use static_assertions::const_assert;
+
+const SIGNATURE: [u8; 6] = *b"RUSREU";
+/// Bumped whenever the layout of the header, directory or BOM changes
+const VERSION: u8 = 1;
+/// Named allocations the directory can hold
+pub const MAX_NAMED: usize = 32;
+/// Longest allocation name
+pub const MAX_NAMED_LEN: usize = 16;
+
+/// Stored at `PERSISTENT_HEADER_ADDRESS`, tells a restarted program the REU contents are valid
+#[derive(Clone, Copy, crate::ReuSafe)]
+struct Header {
+    signature: [u8; 6],
+    version: u8,
+    directory: u32, // address of the directory chunk
+}
+
+const_assert!(size_of::<Header>() <= PERSISTENT_HEADER_SIZE);
+
+/// Directory entry, unused when `len` is 0, a chunk kept by `persist` when `name` is all zeros
+#[derive(Clone, Copy, crate::ReuSafe)]
+struct NamedEntry {
+    name: [u8; MAX_NAMED_LEN],
+    address: u32,
+    len: u32,
+}
+
+const ENTRY_SIZE: u32 = size_of::<NamedEntry>() as u32;
+
+/// The allocator works on the selected expansion memory, see `expansion_memory::select_memory`
+impl RamExpanstionUnit {
+    /// Start the allocator in persistent mode, see `open_allocator` of `dyn ExpansionMemory`
+    pub fn open_allocator(&self) -> bool {
+        expansion_memory::memory().open_allocator()
+    }
+
+    /// Chunk allocated under `name`, allocating `size` bytes if there's none yet
+    pub fn named_alloc(&self, name: &[u8], size: u32) -> ManuallyDrop<ReuChunk> {
+        expansion_memory::memory().named_alloc(name, size)
+    }
+
+    /// Chunk allocated under `name` by this or an earlier run of the program
+    pub fn named_chunk(&self, name: &[u8]) -> Option<ManuallyDrop<ReuChunk>> {
+        expansion_memory::memory().named_chunk(name)
+    }
+
+    /// Free the chunk allocated under `name`, returns `false` if there's none
+    pub fn named_free(&self, name: &[u8]) -> bool {
+        expansion_memory::memory().named_free(name)
+    }
+
+    /// Keep `chunk` allocated across restarts, see `persist` of `dyn ExpansionMemory`
+    pub fn persist(&self, chunk: ReuChunk) -> (u32, u32) {
+        expansion_memory::memory().persist(chunk)
+    }
+
+    /// Take back a chunk kept by `persist`, see `unpersist` of `dyn ExpansionMemory`
+    ///
+    /// # Safety
+    ///
+    /// Same as for `ReuChunk::from_raw`.
+    pub unsafe fn unpersist(&self, address: u32, len: u32) -> ReuChunk {
+        expansion_memory::memory().unpersist(address, len)
+    }
+}
+
+impl dyn ExpansionMemory {
+    /// Start the allocator in persistent mode
+    ///
+    /// If REU still holds what an earlier `open_allocator` set up, e.g.
+    /// before a reset or when another program of a suite was loaded, the
+    /// named allocations and the chunks kept by `persist` stay valid and
+    /// `true` is returned. Everything else is reclaimed, as chunks that were
+    /// live when the earlier program ended have no owner anymore; that
+    /// includes chunks given up with `ReuChunk::into_raw` or `leak`.
+    /// Otherwise the allocator is initialized like with `init_allocator`
+    /// and an empty directory is created.
+    pub fn open_allocator(&self) -> bool {
+        if let Some(header) = self.header() {
+            let directory = (header.directory, MAX_NAMED as u32 * ENTRY_SIZE);
+            let live = self
+                .named_entries()
+                .filter(|(_, entry)| entry.len != 0)
+                .map(|(_, entry)| (entry.address, entry.len));
+            self.rebuild_bom(core::iter::once(directory).chain(live));
+            return true;
+        }
+        self.init_allocator();
+        let directory = self.alloc(MAX_NAMED as u32 * ENTRY_SIZE);
+        self.fill(directory.address, directory.len(), 0);
+        let header = Header {
+            signature: SIGNATURE,
+            version: VERSION,
+            directory: directory.into_raw().0,
+        };
+        self.write(
+            &header as *const Header as usize,
+            PERSISTENT_HEADER_ADDRESS,
+            size_of::<Header>(),
+        );
+        false
+    }
+
+    /// Chunk allocated under `name`, allocating `size` bytes if there's none yet
+    ///
+    /// The chunk is never freed on drop, only by `named_free`. An existing
+    /// chunk is returned as it is, whatever its size.
+    pub fn named_alloc(&self, name: &[u8], size: u32) -> ManuallyDrop<ReuChunk> {
+        if let Some(chunk) = self.named_chunk(name) {
+            return chunk;
+        }
+        let key = named_key(name);
+        let (index, _) = self
+            .named_entries()
+            .find(|(_, entry)| entry.len == 0)
+            .expect("reu directory full");
+        let (address, len) = self.alloc(size).into_raw();
+        self.write_entry(
+            index,
+            &NamedEntry {
+                name: key,
+                address,
+                len,
+            },
+        );
+        ManuallyDrop::new(unsafe { ReuChunk::from_raw(address, len) })
+    }
+
+    /// Chunk allocated under `name` by this or an earlier run of the program
+    pub fn named_chunk(&self, name: &[u8]) -> Option<ManuallyDrop<ReuChunk>> {
+        let key = named_key(name);
+        self.named_entries()
+            .find(|(_, entry)| entry.len != 0 && entry.name == key)
+            .map(|(_, entry)| {
+                ManuallyDrop::new(unsafe { ReuChunk::from_raw(entry.address, entry.len) })
+            })
+    }
+
+    /// Free the chunk allocated under `name`, returns `false` if there's none
+    pub fn named_free(&self, name: &[u8]) -> bool {
+        let key = named_key(name);
+        match self
+            .named_entries()
+            .find(|(_, entry)| entry.len != 0 && entry.name == key)
+        {
+            Some((index, entry)) => {
+                self.write_entry(
+                    index,
+                    &NamedEntry {
+                        name: [0; MAX_NAMED_LEN],
+                        address: 0,
+                        len: 0,
+                    },
+                );
+                drop(unsafe { ReuChunk::from_raw(entry.address, entry.len) });
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Keep `chunk` allocated across restarts, returns its address and length
+    ///
+    /// Like `ReuChunk::into_raw`, but the chunk is recorded in the directory
+    /// without a name, so `open_allocator` doesn't reclaim it. Take it back
+    /// with `unpersist`. It uses up one of the `MAX_NAMED` directory entries.
+    pub fn persist(&self, chunk: ReuChunk) -> (u32, u32) {
+        let (index, _) = self
+            .named_entries()
+            .find(|(_, entry)| entry.len == 0)
+            .expect("reu directory full");
+        let (address, len) = chunk.into_raw();
+        self.write_entry(
+            index,
+            &NamedEntry {
+                name: [0; MAX_NAMED_LEN],
+                address,
+                len,
+            },
+        );
+        (address, len)
+    }
+
+    /// Take back a chunk kept by `persist`, it's freed on drop again
+    ///
+    /// # Safety
+    ///
+    /// Same as for `ReuChunk::from_raw`.
+    pub unsafe fn unpersist(&self, address: u32, len: u32) -> ReuChunk {
+        let (index, _) = self
+            .named_entries()
+            .find(|(_, entry)| {
+                entry.len == len && entry.address == address && entry.name == [0; MAX_NAMED_LEN]
+            })
+            .expect("reu chunk not persisted");
+        self.write_entry(
+            index,
+            &NamedEntry {
+                name: [0; MAX_NAMED_LEN],
+                address: 0,
+                len: 0,
+            },
+        );
+        ReuChunk::from_raw(address, len)
+    }
+
+    /// Header of the persistent allocator, if REU has a valid one
+    fn header(&self) -> Option<Header> {
+        let mut header = MaybeUninit::<Header>::uninit();
+        self.read(
+            header.as_mut_ptr() as usize,
+            PERSISTENT_HEADER_ADDRESS,
+            size_of::<Header>(),
+        );
+        let header = unsafe { header.assume_init() };
+        if header.signature == SIGNATURE && header.version == VERSION {
+            Some(header)
+        } else {
+            None
+        }
+    }
+
+    /// Address of the directory chunk, owned by the header so it's never freed
+    fn directory(&self) -> u32 {
+        let header = self.header().expect("persistent allocator not open");
+        header.directory
+    }
+
+    fn named_entries(&self) -> impl Iterator<Item = (usize, NamedEntry)> + '_ {
+        let directory = self.directory();
+        (0..MAX_NAMED).map(move |i| {
+            let mut entry = MaybeUninit::<NamedEntry>::uninit();
+            self.read(
+                entry.as_mut_ptr() as usize,
+                directory + i as u32 * ENTRY_SIZE,
+                ENTRY_SIZE as usize,
+            );
+            (i, unsafe { entry.assume_init() })
+        })
+    }
+
+    fn write_entry(&self, index: usize, entry: &NamedEntry) {
+        self.write(
+            entry as *const NamedEntry as usize,
+            self.directory() + index as u32 * ENTRY_SIZE,
+            ENTRY_SIZE as usize,
+        );
+    }
+}
+
+/// `name` padded to a directory key
+fn named_key(name: &[u8]) -> [u8; MAX_NAMED_LEN] {
+    assert!(
+        !name.is_empty() && name.len() <= MAX_NAMED_LEN,
+        "reu name length {}",
+        name.len()
+    );
+    let mut key = [0; MAX_NAMED_LEN];
+    key[..name.len()].copy_from_slice(name);
+    key
+}
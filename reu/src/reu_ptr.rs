@@ -0,0 +1,162 @@
+use crate::reu_allocator::ReuChunk;
+use crate::reu_pager::{with_pager, PAGE_SIZE};
+use crate::reu_safe::ReuSafe;
+use core::marker::PhantomData;
+use core::mem::{size_of, MaybeUninit};
+use core::ops::{Add, Sub};
+use core::ptr;
+
+/// A typed pointer into REU memory
+///
+/// Dereferencing goes through the global pager (see `reu_pager::init_pager`),
+/// so data structures such as linked lists or trees can live in REU and be
+/// walked without any manual transfers. `ReuPtr` is itself `ReuSafe`, so it
+/// can be stored in REU-resident nodes. Address 0 is never allocated and
+/// serves as the null pointer.
+pub struct ReuPtr<T> {
+    address: u32,
+    _marker: PhantomData<T>,
+}
+
+impl<T> Clone for ReuPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for ReuPtr<T> {}
+
+impl<T> PartialEq for ReuPtr<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.address == other.address
+    }
+}
+
+impl<T> Eq for ReuPtr<T> {}
+
+unsafe impl<T: 'static> ReuSafe for ReuPtr<T> {}
+
+impl<T> ReuPtr<T> {
+    /// Pointer to REU `address`
+    pub const fn new(address: u32) -> Self {
+        ReuPtr {
+            address,
+            _marker: PhantomData,
+        }
+    }
+
+    pub const fn null() -> Self {
+        Self::new(0)
+    }
+
+    /// Pointer to the start of `chunk`, it must outlive all uses of the pointer
+    pub fn from_chunk(chunk: &ReuChunk) -> Self {
+        Self::new(chunk.address)
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.address == 0
+    }
+
+    /// 24-bit REU address
+    pub fn address(&self) -> u32 {
+        self.address
+    }
+
+    /// Pointer `count` elements further, negative `count` goes back
+    pub fn offset(self, count: i32) -> Self {
+        let bytes = count * size_of::<T>() as i32;
+        Self::new((self.address as i32 + bytes) as u32)
+    }
+
+    /// Same address, different pointee type
+    pub fn cast<U>(self) -> ReuPtr<U> {
+        ReuPtr::new(self.address)
+    }
+}
+
+/// Pointer `count` elements further
+impl<T> Add<u32> for ReuPtr<T> {
+    type Output = Self;
+
+    fn add(self, count: u32) -> Self {
+        Self::new(self.address + count * size_of::<T>() as u32)
+    }
+}
+
+/// Pointer `count` elements back
+impl<T> Sub<u32> for ReuPtr<T> {
+    type Output = Self;
+
+    fn sub(self, count: u32) -> Self {
+        Self::new(self.address - count * size_of::<T>() as u32)
+    }
+}
+
+impl<T: ReuSafe> ReuPtr<T> {
+    /// Copy the pointee into RAM
+    pub fn read(&self) -> T {
+        let mut value = MaybeUninit::<T>::uninit();
+        copy_pages(
+            self.address,
+            value.as_mut_ptr() as *mut u8,
+            size_of::<T>(),
+            false,
+        );
+        unsafe { value.assume_init() }
+    }
+
+    /// Overwrite the pointee
+    pub fn write(&self, value: T) {
+        copy_pages(
+            self.address,
+            &value as *const T as *mut u8,
+            size_of::<T>(),
+            true,
+        );
+    }
+
+    /// Modify the pointee in place
+    pub fn update<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let mut value = self.read();
+        let result = f(&mut value);
+        self.write(value);
+        result
+    }
+}
+
+impl<T> ufmt::uDebug for ReuPtr<T> {
+    fn fmt<W: ufmt::uWrite + ?Sized>(
+        &self,
+        f: &mut ufmt::Formatter<'_, W>,
+    ) -> Result<(), W::Error> {
+        f.write_char('^')?;
+        self.address.fmt(f)
+    }
+}
+
+/// Copy `len` bytes between REU `address` and RAM page by page,
+/// into REU if `write` is set
+fn copy_pages(address: u32, ram: *mut u8, len: usize, write: bool) {
+    with_pager(|pager| {
+        let mut done = 0;
+        while done < len {
+            let at = address + done as u32;
+            let in_page = PAGE_SIZE - (at as usize & (PAGE_SIZE - 1));
+            let count = if len - done < in_page {
+                len - done
+            } else {
+                in_page
+            };
+            let mapped = pager.map(at, write);
+            unsafe {
+                if write {
+                    ptr::copy_nonoverlapping(ram.add(done), mapped, count);
+                } else {
+                    ptr::copy_nonoverlapping(mapped, ram.add(done), count);
+                }
+            }
+            done += count;
+        }
+    });
+}
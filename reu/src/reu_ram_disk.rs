@@ -0,0 +1,252 @@
+extern crate alloc;
+
+use crate::expansion_memory;
+use crate::reu_allocator::ReuChunk;
+use alloc::vec::Vec;
+
+/// Longest file name, same as on a 1541
+pub const MAX_NAME_LEN: usize = 16;
+/// Smallest chunk a file grows to, one allocation unit
+const MIN_FILE_CHUNK: u32 = 256;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    /// No file with that name
+    NotFound,
+    /// A file with that name already exists
+    Exists,
+    /// Name longer than `MAX_NAME_LEN` or empty
+    BadName,
+    /// Handle of a deleted file
+    BadHandle,
+    /// Seek past the end of the file
+    BadPosition,
+}
+
+/// An open file of a `RamDisk` and the position the next read or write starts at
+#[derive(Clone, Copy)]
+pub struct FileHandle {
+    entry: usize,
+    generation: u16, // of the entry when the file was opened
+    position: u32,
+}
+
+impl FileHandle {
+    /// Offset of the next byte to read or write
+    pub fn position(&self) -> u32 {
+        self.position
+    }
+}
+
+/// Directory entry, the file contents are in its chunk
+struct DirEntry {
+    name: [u8; MAX_NAME_LEN],
+    name_len: u8,
+    chunk: Option<ReuChunk>, // None until something is written
+    len: u32,
+    generation: u16, // tells handles of a deleted file from those of a file created in its slot
+}
+
+impl DirEntry {
+    fn name(&self) -> &[u8] {
+        &self.name[..self.name_len as usize]
+    }
+}
+
+/// A small filesystem in REU memory
+///
+/// The directory is kept in RAM, each file is a single chunk from the REU
+/// allocator. A file grows by moving it to a chunk twice the size, so
+/// appending stays cheap. Reads and writes are single DMA transfers
+/// straight between the caller's buffer and the file.
+///
+/// To construct use `new`
+pub struct RamDisk {
+    entries: Vec<Option<DirEntry>>, // deleted files leave a hole so handles stay put
+    generation: u16,                // given to the next file created
+}
+
+impl Default for RamDisk {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RamDisk {
+    pub fn new() -> Self {
+        RamDisk {
+            entries: Vec::new(),
+            generation: 0,
+        }
+    }
+
+    /// Create an empty file and open it
+    pub fn create(&mut self, name: &[u8]) -> Result<FileHandle, FsError> {
+        if name.is_empty() || name.len() > MAX_NAME_LEN {
+            return Err(FsError::BadName);
+        }
+        if self.find(name).is_some() {
+            return Err(FsError::Exists);
+        }
+        let generation = self.generation;
+        self.generation = generation.wrapping_add(1);
+        let mut entry = DirEntry {
+            name: [0; MAX_NAME_LEN],
+            name_len: name.len() as u8,
+            chunk: None,
+            len: 0,
+            generation,
+        };
+        entry.name[..name.len()].copy_from_slice(name);
+
+        let index = match self.entries.iter().position(|e| e.is_none()) {
+            Some(index) => {
+                self.entries[index] = Some(entry);
+                index
+            }
+            None => {
+                self.entries.push(Some(entry));
+                self.entries.len() - 1
+            }
+        };
+        Ok(FileHandle {
+            entry: index,
+            generation,
+            position: 0,
+        })
+    }
+
+    /// Open an existing file at its start
+    pub fn open(&self, name: &[u8]) -> Result<FileHandle, FsError> {
+        match self.find(name) {
+            Some(index) => Ok(FileHandle {
+                entry: index,
+                generation: self.entries[index].as_ref().unwrap().generation,
+                position: 0,
+            }),
+            None => Err(FsError::NotFound),
+        }
+    }
+
+    /// Remove a file and free its REU memory
+    ///
+    /// Handles of the file are refused with `FsError::BadHandle` afterwards,
+    /// even when a file created later takes over its directory slot.
+    pub fn delete(&mut self, name: &[u8]) -> Result<(), FsError> {
+        match self.find(name) {
+            Some(index) => {
+                self.entries[index] = None;
+                Ok(())
+            }
+            None => Err(FsError::NotFound),
+        }
+    }
+
+    pub fn exists(&self, name: &[u8]) -> bool {
+        self.find(name).is_some()
+    }
+
+    /// Names and lengths of all files
+    pub fn list(&self) -> impl Iterator<Item = (&[u8], u32)> {
+        self.entries
+            .iter()
+            .flatten()
+            .map(|entry| (entry.name(), entry.len))
+    }
+
+    /// Length of an open file in bytes
+    pub fn file_len(&self, handle: &FileHandle) -> Result<u32, FsError> {
+        Ok(self.entry(handle)?.len)
+    }
+
+    /// Move to `position`, which can be at most the length of the file
+    pub fn seek(&self, handle: &mut FileHandle, position: u32) -> Result<(), FsError> {
+        if position > self.entry(handle)?.len {
+            return Err(FsError::BadPosition);
+        }
+        handle.position = position;
+        Ok(())
+    }
+
+    /// Fill `buffer` from the current position, returns the number of bytes
+    /// read, which is less than the buffer length at the end of the file
+    pub fn read(&self, handle: &mut FileHandle, buffer: &mut [u8]) -> Result<usize, FsError> {
+        let entry = self.entry(handle)?;
+        let left = entry.len.saturating_sub(handle.position);
+        let count = if (buffer.len() as u32) < left {
+            buffer.len()
+        } else {
+            left as usize
+        };
+        if let Some(chunk) = &entry.chunk {
+            chunk.pull_at(
+                expansion_memory::memory(),
+                buffer.as_mut_ptr() as usize,
+                handle.position,
+                count,
+            );
+        }
+        handle.position += count as u32;
+        Ok(count)
+    }
+
+    /// Write `data` at the current position, growing the file as needed
+    pub fn write(&mut self, handle: &mut FileHandle, data: &[u8]) -> Result<(), FsError> {
+        self.entry(handle)?;
+        let end = handle.position + data.len() as u32;
+        self.reserve(handle.entry, end);
+
+        let entry = self.entries[handle.entry].as_mut().unwrap();
+        if let Some(chunk) = &entry.chunk {
+            chunk.push_at(
+                expansion_memory::memory(),
+                data.as_ptr() as usize,
+                handle.position,
+                data.len(),
+            );
+        }
+        if end > entry.len {
+            entry.len = end;
+        }
+        handle.position = end;
+        Ok(())
+    }
+
+    /// Make the chunk of entry `index` hold at least `size` bytes
+    fn reserve(&mut self, index: usize, size: u32) {
+        let entry = self.entries[index].as_mut().unwrap();
+        let capacity = entry.chunk.as_ref().map_or(0, |chunk| chunk.len());
+        if size <= capacity {
+            return;
+        }
+        let mut new_capacity = if capacity < MIN_FILE_CHUNK {
+            MIN_FILE_CHUNK
+        } else {
+            capacity * 2
+        };
+        if new_capacity < size {
+            new_capacity = size;
+        }
+
+        let reu = expansion_memory::memory();
+        let new_chunk = reu.alloc(new_capacity);
+        if let Some(chunk) = &entry.chunk {
+            let mut buffer = [0u8; 128];
+            reu.copy(chunk.address, new_chunk.address, entry.len, &mut buffer);
+        }
+        entry.chunk = Some(new_chunk);
+    }
+
+    fn find(&self, name: &[u8]) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|entry| entry.as_ref().is_some_and(|entry| entry.name() == name))
+    }
+
+    fn entry(&self, handle: &FileHandle) -> Result<&DirEntry, FsError> {
+        match self.entries.get(handle.entry) {
+            Some(Some(entry)) if entry.generation == handle.generation => Ok(entry),
+            _ => Err(FsError::BadHandle),
+        }
+    }
+}
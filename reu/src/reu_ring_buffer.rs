@@ -0,0 +1,180 @@
+use crate::expansion_memory;
+use crate::reu_allocator::ReuChunk;
+use crate::reu_safe::ReuSafe;
+use crate::without_interrupts;
+use core::mem::{size_of, MaybeUninit};
+use core::slice;
+
+/// What a write does when the buffer has no room left
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Drop the oldest bytes to make room
+    Overwrite,
+    /// Refuse the whole write with `RingBufferError::Full`
+    Error,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum RingBufferError {
+    /// Not enough room for the write, only with `OverflowPolicy::Error`
+    Full,
+}
+
+/// A FIFO byte queue stored in REU memory
+///
+/// Writes and reads are DMA transfers of contiguous spans, two of them when
+/// the data wraps around the end of the chunk. Typed records are pushed as
+/// their bytes, so with `OverflowPolicy::Overwrite` the capacity should be a
+/// multiple of the record size.
+///
+/// Transfers run with interrupts disabled, so an interrupt handler using
+/// the REU can't change the registers halfway through one. Both ends take
+/// `&mut self`, so the producer and consumer must run in the same context.
+///
+/// To construct use `with_capacity`
+pub struct REURingBuffer {
+    chunk: ReuChunk,
+    capacity: u32,
+    head: u32, // offset of the oldest byte
+    len: u32,  // bytes stored
+    policy: OverflowPolicy,
+}
+
+impl REURingBuffer {
+    /// Allocate a buffer of `capacity` bytes in REU
+    pub fn with_capacity(capacity: u32, policy: OverflowPolicy) -> Self {
+        REURingBuffer {
+            chunk: expansion_memory::memory().alloc(capacity),
+            capacity,
+            head: 0,
+            len: 0,
+            policy,
+        }
+    }
+
+    /// Number of bytes stored
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    /// Number of bytes that can be written without overflowing
+    pub fn free(&self) -> u32 {
+        self.capacity - self.len()
+    }
+
+    /// Drop all stored bytes
+    pub fn clear(&mut self) {
+        without_interrupts(|| {
+            self.head = 0;
+            self.len = 0;
+        });
+    }
+
+    /// Append a byte or a typed record
+    pub fn push_back<T: ReuSafe>(&mut self, item: T) -> Result<(), RingBufferError> {
+        let bytes =
+            unsafe { slice::from_raw_parts(&item as *const T as *const u8, size_of::<T>()) };
+        self.write(bytes).map(|_| ())
+    }
+
+    /// Remove the oldest byte or typed record, `None` if not enough bytes are stored
+    pub fn pop_front<T: ReuSafe>(&mut self) -> Option<T> {
+        let mut item = MaybeUninit::<T>::uninit();
+        without_interrupts(|| {
+            if self.len < size_of::<T>() as u32 {
+                return None;
+            }
+            let bytes =
+                unsafe { slice::from_raw_parts_mut(item.as_mut_ptr() as *mut u8, size_of::<T>()) };
+            self.take(bytes);
+            Some(unsafe { item.assume_init() })
+        })
+    }
+
+    /// Append all of `data`, returns the number of bytes written
+    pub fn write(&mut self, data: &[u8]) -> Result<usize, RingBufferError> {
+        let total = data.len();
+        let mut data = data;
+        if self.policy == OverflowPolicy::Overwrite && data.len() as u32 > self.capacity {
+            // only the newest bytes survive anyway
+            data = &data[data.len() - self.capacity as usize..];
+        }
+
+        without_interrupts(|| {
+            let needed = data.len() as u32;
+            let free = self.capacity - self.len;
+            if needed > free {
+                match self.policy {
+                    OverflowPolicy::Error => return Err(RingBufferError::Full),
+                    OverflowPolicy::Overwrite => self.skip(needed - free),
+                }
+            }
+            self.put(data);
+            Ok(total)
+        })
+    }
+
+    /// Move up to `buffer.len()` oldest bytes into `buffer`, returns how many were read
+    pub fn read(&mut self, buffer: &mut [u8]) -> usize {
+        without_interrupts(|| {
+            let count = if (buffer.len() as u32) < self.len {
+                buffer.len()
+            } else {
+                self.len as usize
+            };
+            self.take(&mut buffer[..count]);
+            count
+        })
+    }
+
+    /// Store `data` after the newest byte, there must be room for it
+    fn put(&mut self, data: &[u8]) {
+        let tail = (self.head + self.len) % self.capacity;
+        let first = self.span(tail, data.len());
+        let reu = expansion_memory::memory();
+        self.chunk.push_at(reu, data.as_ptr() as usize, tail, first);
+        if first < data.len() {
+            self.chunk
+                .push_at(reu, data[first..].as_ptr() as usize, 0, data.len() - first);
+        }
+        self.len += data.len() as u32;
+    }
+
+    /// Move `buffer.len()` oldest bytes into `buffer`, they must be stored
+    fn take(&mut self, buffer: &mut [u8]) {
+        let first = self.span(self.head, buffer.len());
+        let reu = expansion_memory::memory();
+        self.chunk
+            .pull_at(reu, buffer.as_mut_ptr() as usize, self.head, first);
+        if first < buffer.len() {
+            let rest = buffer.len() - first;
+            self.chunk
+                .pull_at(reu, buffer[first..].as_mut_ptr() as usize, 0, rest);
+        }
+        self.skip(buffer.len() as u32);
+    }
+
+    /// Drop `count` oldest bytes
+    fn skip(&mut self, count: u32) {
+        self.head = (self.head + count) % self.capacity;
+        self.len -= count;
+    }
+
+    /// How many of `count` bytes fit between `offset` and the end of the chunk
+    fn span(&self, offset: u32, count: usize) -> usize {
+        let to_end = self.capacity - offset;
+        if (count as u32) < to_end {
+            count
+        } else {
+            to_end as usize
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use core::mem::MaybeUninit;
+
+/// Types that can be moved between RAM and REU as plain bytes
+///
+/// `REUArray`, `ReuChunk::read`/`write` and the other REU containers only
+/// accept `ReuSafe` types. Use `#[derive(Clone, Copy, ReuSafe)]` on your own
+/// structs, the derive checks that all fields are `ReuSafe` too.
+///
+/// Since a `ReuSafe` type is `Copy` it has no destructor, so containers can
+/// forget elements (`pop`, `truncate`, drop of the container) without
+/// anything to run.
+///
+/// # Safety
+///
+/// The type must not contain pointers or references: RAM they point to
+/// can be reused while the value sits in REU. Every bit pattern must be a
+/// valid value, because containers read elements straight from REU bytes,
+/// which may come from a file or another program. That rules out `bool`,
+/// `char` and enums; store them as integers instead.
+pub unsafe trait ReuSafe: Copy + 'static {}
+
+macro_rules! reu_safe {
+    ($($t:ty),*) => {
+        $(unsafe impl ReuSafe for $t {})*
+    };
+}
+
+reu_safe!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+reu_safe!(f32, f64, ());
+
+unsafe impl<T: ReuSafe, const N: usize> ReuSafe for [T; N] {}
+unsafe impl<T: ReuSafe> ReuSafe for MaybeUninit<T> {}
+unsafe impl<A: ReuSafe, B: ReuSafe> ReuSafe for (A, B) {}
+unsafe impl<A: ReuSafe, B: ReuSafe, C: ReuSafe> ReuSafe for (A, B, C) {}
+unsafe impl<A: ReuSafe, B: ReuSafe, C: ReuSafe, D: ReuSafe> ReuSafe for (A, B, C, D) {}
@@ -0,0 +1,181 @@
+extern crate alloc;
+
+use crate::expansion_memory;
+use crate::reu_allocator::ReuChunk;
+use alloc::vec::Vec;
+
+/// Object sizes served by the slab, anything up to 256 bytes fits one of them
+const SIZE_CLASSES: [u16; 7] = [4, 8, 16, 32, 64, 128, 256];
+/// Largest object, its length minus one still fits the `u8` of a handle
+pub const MAX_OBJECT_LEN: u16 = 256;
+/// Size of one slab taken from the REU allocator for a size class
+const SLAB_SIZE: u32 = 4096;
+/// End of a free list, REU address 0 is never part of the allocation pool
+const NO_OBJECT: u32 = 0;
+
+/// A compact reference to a small object in REU: 24-bit address and length
+#[derive(Clone, Copy, PartialEq, Eq, crate::ReuSafe)]
+pub struct ReuHandle {
+    address: [u8; 3],
+    len_minus_one: u8, // objects are 1 to 256 bytes long
+}
+
+impl ReuHandle {
+    fn new(address: u32, len: u16) -> Self {
+        ReuHandle {
+            address: [address as u8, (address >> 8) as u8, (address >> 16) as u8],
+            len_minus_one: (len - 1) as u8,
+        }
+    }
+
+    /// REU address of the object
+    pub fn address(&self) -> u32 {
+        self.address[0] as u32 | (self.address[1] as u32) << 8 | (self.address[2] as u32) << 16
+    }
+
+    /// Length of the object in bytes, never 0
+    fn len(&self) -> u16 {
+        self.len_minus_one as u16 + 1
+    }
+}
+
+impl ufmt::uDebug for ReuHandle {
+    fn fmt<W: ufmt::uWrite + ?Sized>(
+        &self,
+        f: &mut ufmt::Formatter<'_, W>,
+    ) -> Result<(), W::Error> {
+        self.address().fmt(f)?;
+        f.write_char('(')?;
+        self.len().fmt(f)?;
+        f.write_char(')')?;
+        Ok(())
+    }
+}
+
+/// Free list and bump area of one size class
+#[derive(Clone, Copy)]
+struct SizeClass {
+    free: u32,     // first free object, it stores the address of the next one
+    next: u32,     // next never used object in the newest slab
+    slab_end: u32, // end of the newest slab
+}
+
+/// Size-class allocator for small objects in REU
+///
+/// The BOM allocator hands out 256-byte units, which wastes most of it on
+/// short strings or records. The slab takes 4KB slabs from the BOM allocator
+/// and cuts them into objects of up to 256 bytes, in size classes of 4 to
+/// 256 bytes. Freed objects go to a per-class free list that is linked
+/// through the objects themselves, so only the list heads live in RAM.
+///
+/// Slabs are returned to the BOM allocator when the slab is dropped, which
+/// makes all its handles invalid.
+pub struct ReuSlab {
+    classes: [SizeClass; SIZE_CLASSES.len()],
+    slabs: Vec<ReuChunk>,
+}
+
+impl Default for ReuSlab {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReuSlab {
+    pub fn new() -> Self {
+        ReuSlab {
+            classes: [SizeClass {
+                free: NO_OBJECT,
+                next: 0,
+                slab_end: 0,
+            }; SIZE_CLASSES.len()],
+            slabs: Vec::new(),
+        }
+    }
+
+    /// Allocate an object of `len` bytes, at most `MAX_OBJECT_LEN`
+    pub fn alloc(&mut self, len: u16) -> ReuHandle {
+        if len == 0 {
+            panic!("reu 0 alloc");
+        }
+        assert!(len <= MAX_OBJECT_LEN, "slab alloc {}", len);
+        let class_index = SIZE_CLASSES.iter().position(|size| *size >= len).unwrap();
+        let size = SIZE_CLASSES[class_index] as u32;
+        let class = &mut self.classes[class_index];
+
+        let address = if class.free != NO_OBJECT {
+            let address = class.free;
+            class.free = read_address(address);
+            address
+        } else {
+            if class.next + size > class.slab_end {
+                let slab = expansion_memory::memory().alloc(SLAB_SIZE);
+                class.next = slab.address;
+                class.slab_end = slab.address + SLAB_SIZE;
+                self.slabs.push(slab);
+            }
+            let address = class.next;
+            class.next += size;
+            address
+        };
+        ReuHandle::new(address, len)
+    }
+
+    /// Length of the object in bytes, as passed to `alloc`
+    pub fn len(&self, handle: ReuHandle) -> u16 {
+        handle.len()
+    }
+
+    /// Return an object to its size class
+    pub fn free(&mut self, handle: ReuHandle) {
+        let class_index = SIZE_CLASSES
+            .iter()
+            .position(|size| *size >= handle.len())
+            .unwrap();
+        let class = &mut self.classes[class_index];
+        write_address(handle.address(), class.free);
+        class.free = handle.address();
+    }
+
+    /// Store `data` in the object, it must not be longer than the object
+    pub fn write(&self, handle: ReuHandle, data: &[u8]) {
+        assert!(
+            data.len() <= handle.len() as usize,
+            "slab write {}/{}",
+            data.len(),
+            handle.len()
+        );
+        if !data.is_empty() {
+            let reu = expansion_memory::memory();
+            reu.write(data.as_ptr() as usize, handle.address(), data.len());
+        }
+    }
+
+    /// Copy the object into `buffer`, returns the number of bytes copied
+    pub fn read(&self, handle: ReuHandle, buffer: &mut [u8]) -> usize {
+        let count = if buffer.len() < handle.len() as usize {
+            buffer.len()
+        } else {
+            handle.len() as usize
+        };
+        if count > 0 {
+            let reu = expansion_memory::memory();
+            reu.read(buffer.as_mut_ptr() as usize, handle.address(), count);
+        }
+        count
+    }
+}
+
+/// Free list link stored in the first 3 bytes of a free object
+fn read_address(address: u32) -> u32 {
+    let mut bytes = [0u8; 3];
+    let reu = expansion_memory::memory();
+    reu.read(bytes.as_mut_ptr() as usize, address, 3);
+    bytes[0] as u32 | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16
+}
+
+fn write_address(address: u32, next: u32) {
+    let bytes = [next as u8, (next >> 8) as u8, (next >> 16) as u8];
+    let reu = expansion_memory::memory();
+    reu.write(bytes.as_ptr() as usize, address, 3);
+}
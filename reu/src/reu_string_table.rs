@@ -0,0 +1,278 @@
+use crate::expansion_memory::{self, ExpansionMemory, ReadMemory};
+use crate::reu_allocator::ReuChunk;
+use crate::reu_safe::ReuSafe;
+use core::mem::{size_of, ManuallyDrop, MaybeUninit};
+
+/// Size of a string's length prefix, of an index entry and of the string count
+const PREFIX_SIZE: u32 = 2;
+const ENTRY_SIZE: u32 = 4;
+const COUNT_SIZE: u32 = 2;
+
+/// Number of a string in an `REUStringTable`, in the order they were added
+#[derive(Clone, Copy, PartialEq, Eq, crate::ReuSafe)]
+pub struct StringId(pub u16);
+
+/// A store of byte strings in REU memory
+///
+/// Strings are appended to a pool chunk, each with a 16-bit length prefix,
+/// and an index chunk maps every `StringId` to its offset in the pool, so
+/// only a few bytes of bookkeeping live in RAM. Strings are kept as bytes
+/// and not checked, PETSCII text or binary blobs work as well as ASCII.
+///
+/// A table prepared offline can also be read straight from any
+/// `ReadMemory`, e.g. cartridge flash, see `from_memory`.
+///
+/// To construct use `with_capacity`
+pub struct REUStringTable {
+    pool: ManuallyDrop<ReuChunk>,  // owned unless the table is read-only
+    index: ManuallyDrop<ReuChunk>, // owned unless the table is read-only
+    source: Option<&'static dyn ReadMemory>, // memory of a read-only table
+    used: u32,
+    count: u16,
+}
+
+impl REUStringTable {
+    /// Allocate room for `bytes` bytes of strings and up to `strings` strings
+    pub fn with_capacity(bytes: u32, strings: u16) -> Self {
+        let reu = expansion_memory::memory();
+        REUStringTable {
+            pool: ManuallyDrop::new(reu.alloc(bytes + strings as u32 * PREFIX_SIZE)),
+            index: ManuallyDrop::new(reu.alloc(strings as u32 * ENTRY_SIZE)),
+            source: None,
+            used: 0,
+            count: 0,
+        }
+    }
+
+    /// Read-only table stored at `address` of `source`
+    ///
+    /// This is for tables prepared offline, e.g. on an `EasyFlash` cartridge.
+    /// The table starts with a 16-bit string count, followed by a 32-bit
+    /// offset for each string, counted from the start of the table, and the
+    /// strings themselves, each with a 16-bit length prefix like the blobs
+    /// `load` takes. All numbers are little-endian. Adding strings panics.
+    pub fn from_memory(source: &'static dyn ReadMemory, address: u32) -> Self {
+        let mut count = MaybeUninit::<u16>::uninit();
+        source.read(count.as_mut_ptr() as usize, address, COUNT_SIZE as usize);
+        let count = unsafe { count.assume_init() };
+        let index_len = count as u32 * ENTRY_SIZE;
+        assert!(
+            address + COUNT_SIZE + index_len <= source.size(),
+            "string table outside memory"
+        );
+        // neither is ever freed, as the table doesn't own them
+        unsafe {
+            REUStringTable {
+                pool: ManuallyDrop::new(ReuChunk::from_raw(address, source.size() - address)),
+                index: ManuallyDrop::new(ReuChunk::from_raw(address + COUNT_SIZE, index_len)),
+                source: Some(source),
+                used: 0,
+                count,
+            }
+        }
+    }
+
+    /// Number of strings in the table
+    pub fn len(&self) -> u16 {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Store a copy of `bytes`, returns the ID to get it back with
+    pub fn add(&mut self, bytes: &[u8]) -> StringId {
+        let size = PREFIX_SIZE + bytes.len() as u32;
+        self.reserve(1, size);
+        let offset = self.used;
+        self.pool.write(offset, &(bytes.len() as u16));
+        self.pool.push_at(
+            self.writable_memory(),
+            bytes.as_ptr() as usize,
+            offset + PREFIX_SIZE,
+            bytes.len(),
+        );
+        self.index.write(self.count as u32 * ENTRY_SIZE, &offset);
+        self.used += size;
+        self.count += 1;
+        StringId(self.count - 1)
+    }
+
+    /// Add all strings of `blob`, returns the ID of the first one
+    ///
+    /// The blob has the same layout as the pool: each string is a 16-bit
+    /// little-endian length followed by its bytes. That lets a table built
+    /// offline, e.g. all texts of an adventure, be loaded with a single DMA
+    /// transfer; only the index is built string by string.
+    pub fn load(&mut self, blob: &[u8]) -> StringId {
+        self.reserve(0, blob.len() as u32);
+        let first = self.count;
+        let start = self.used;
+        let mut entries = [0u32; 32];
+        let mut pending = 0;
+        let mut at = 0;
+        while at < blob.len() {
+            assert!(at + 2 <= blob.len(), "bad string blob at {}", at);
+            let len = u16::from_le_bytes([blob[at], blob[at + 1]]) as usize;
+            assert!(at + 2 + len <= blob.len(), "bad string blob at {}", at);
+            self.reserve(1, 0);
+
+            entries[pending] = start + at as u32;
+            pending += 1;
+            self.count += 1;
+            if pending == entries.len() {
+                self.push_entries(&entries, self.count - pending as u16);
+                pending = 0;
+            }
+            at += 2 + len;
+        }
+        self.push_entries(&entries[..pending], self.count - pending as u16);
+
+        self.pool.push_at(
+            self.writable_memory(),
+            blob.as_ptr() as usize,
+            start,
+            blob.len(),
+        );
+        self.used += blob.len() as u32;
+        StringId(first)
+    }
+
+    /// Length of a string in bytes
+    pub fn string_len(&self, id: StringId) -> u16 {
+        self.read(&self.pool, self.offset(id))
+    }
+
+    /// Copy a string into `buffer`, returns the number of bytes copied
+    ///
+    /// A string longer than `buffer` is cut off.
+    pub fn get(&self, id: StringId, buffer: &mut [u8]) -> usize {
+        let offset = self.offset(id);
+        let len: u16 = self.read(&self.pool, offset);
+        let count = if buffer.len() < len as usize {
+            buffer.len()
+        } else {
+            len as usize
+        };
+        self.pull_at(buffer.as_mut_ptr() as usize, offset + PREFIX_SIZE, count);
+        count
+    }
+
+    /// Wrap a string for printing with `ufmt`, e.g. `println!("{}", table.display(id))`
+    ///
+    /// The string is pulled through a small buffer, so it can be longer than
+    /// any RAM buffer the program could spare. The bytes go to the writer
+    /// unchanged, so PETSCII prints as is on a writer that passes bytes on
+    /// to CHROUT, like `ufmt_stdio`'s console.
+    pub fn display(&self, id: StringId) -> REUStringDisplay {
+        REUStringDisplay { table: self, id }
+    }
+
+    fn offset(&self, id: StringId) -> u32 {
+        assert!(id.0 < self.count, "string id {}/{}", id.0, self.count);
+        self.read(&self.index, id.0 as u32 * ENTRY_SIZE)
+    }
+
+    /// Read a `T` stored `offset` bytes into `chunk`, which is `pool` or `index`
+    fn read<T: ReuSafe>(&self, chunk: &ReuChunk, offset: u32) -> T {
+        match self.source {
+            Some(source) => {
+                let mut value = MaybeUninit::<T>::uninit();
+                source.read(
+                    value.as_mut_ptr() as usize,
+                    chunk.address + offset,
+                    size_of::<T>(),
+                );
+                unsafe { value.assume_init() }
+            }
+            None => chunk.read(offset),
+        }
+    }
+
+    /// Pull `length` bytes starting `offset` bytes into the pool into C64 RAM
+    fn pull_at(&self, c64_start: usize, offset: u32, length: usize) {
+        match self.source {
+            // bounds were checked when the table was opened
+            Some(source) if length > 0 => {
+                source.read(c64_start, self.pool.address + offset, length)
+            }
+            Some(_) => {}
+            None => self
+                .pool
+                .pull_at(expansion_memory::memory(), c64_start, offset, length),
+        }
+    }
+
+    /// The selected expansion memory holding the table, panics if it's read-only
+    fn writable_memory(&self) -> &'static dyn ExpansionMemory {
+        assert!(self.source.is_none(), "read-only string table");
+        expansion_memory::memory()
+    }
+
+    /// Panic unless `strings` more strings taking `bytes` bytes fit
+    fn reserve(&self, strings: u16, bytes: u32) {
+        assert!(self.source.is_none(), "read-only string table");
+        assert!(
+            (self.count as u32 + strings as u32) * ENTRY_SIZE <= self.index.len()
+                && self.used + bytes <= self.pool.len(),
+            "string table full"
+        );
+    }
+
+    fn push_entries(&self, entries: &[u32], first: u16) {
+        self.index.push_at(
+            self.writable_memory(),
+            entries.as_ptr() as usize,
+            first as u32 * ENTRY_SIZE,
+            entries.len() * ENTRY_SIZE as usize,
+        );
+    }
+}
+
+impl Drop for REUStringTable {
+    fn drop(&mut self) {
+        if self.source.is_none() {
+            unsafe {
+                ManuallyDrop::drop(&mut self.pool);
+                ManuallyDrop::drop(&mut self.index);
+            }
+        }
+    }
+}
+
+/// A string of an `REUStringTable` that can be printed with `ufmt`
+pub struct REUStringDisplay<'a> {
+    table: &'a REUStringTable,
+    id: StringId,
+}
+
+impl<'a> ufmt::uDisplay for REUStringDisplay<'a> {
+    fn fmt<W: ufmt::uWrite + ?Sized>(
+        &self,
+        f: &mut ufmt::Formatter<'_, W>,
+    ) -> Result<(), W::Error> {
+        let offset = self.table.offset(self.id);
+        let len: u16 = self.table.read(&self.table.pool, offset);
+        let mut buffer = [0u8; 32];
+        let mut done = 0;
+        while done < len as usize {
+            let left = len as usize - done;
+            let count = if left < buffer.len() {
+                left
+            } else {
+                buffer.len()
+            };
+            self.table.pull_at(
+                buffer.as_mut_ptr() as usize,
+                offset + PREFIX_SIZE + done as u32,
+                count,
+            );
+            // the string is PETSCII, not UTF-8; the raw bytes are handed to
+            // the writer, which sends them to the screen byte by byte
+            f.write_str(unsafe { core::str::from_utf8_unchecked(&buffer[..count]) })?;
+            done += count;
+        }
+        Ok(())
+    }
+}
@@ -16,10 +16,55 @@ struct MyState {
     flag: bool,
 }
 
+// xorshift noise for test data
+struct Noise(u16);
+
+impl Noise {
+    fn next(&mut self) -> u16 {
+        self.0 ^= self.0 << 7;
+        self.0 ^= self.0 >> 9;
+        self.0 ^= self.0 << 8;
+        self.0
+    }
+}
+
+// sort `len` noisy (key, position) pairs with a window of `window` elements,
+// checks the order, that equal keys stay in order and nothing got lost
+fn check_sort(len: u32, window: usize) {
+    let mut array = reu::REUArray::<(u8, u16)>::with_capacity(len, window);
+    let mut noise = Noise(0xACE1);
+    let mut sum = 0u32;
+    for i in 0..len {
+        let key = noise.next() as u8 & 0x1F;
+        sum += key as u32;
+        array.push((key, i as u16));
+    }
+    array.sort_by_key(|&(key, _)| key);
+    assert_eq!(array.len(), len);
+    let mut previous = (0u8, 0u16);
+    for (i, item) in array.iter().enumerate() {
+        if i > 0 {
+            assert!(previous.0 < item.0 || (previous.0 == item.0 && previous.1 < item.1));
+        }
+        sum -= item.0 as u32;
+        previous = item;
+    }
+    assert_eq!(sum, 0);
+}
+
 #[mos_test::tests]
 mod tests {
+    use core::ops::Bound;
+    use reu::checksum::{Crc16, Crc32};
+    use reu::lz;
+    use reu::ram_expansion_unit::reu;
+    use reu::{
+        FsError, REUArray, REUBTreeMap, REUHashMap, RamDisk, ReuHeap, ReuSlab, WindowAlignment,
+    };
+
     #[init]
     fn init() -> super::MyState {
+        reu().init_allocator();
         // state initial value
         super::MyState { flag: true }
     }
@@ -50,4 +95,456 @@ mod tests {
         assert!(state.flag);
         state.flag = false;
     }
+
+    // inserting and removing keeps the map the same size
+    #[test]
+    fn hash_map_churn() {
+        let mut map = REUHashMap::<u16, u16>::with_capacity(16);
+        let capacity = map.capacity();
+        map.insert(0xffff, 1);
+        for key in 0..1000 {
+            map.insert(key, key);
+            assert_eq!(map.remove(&key), Some(key));
+        }
+        assert_eq!(map.capacity(), capacity);
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.get(&0xffff), Some(1));
+    }
+
+    #[test]
+    fn lz_empty() {
+        let packed = lz::compress(&[]);
+        assert_eq!(&packed[..], &[0]);
+        assert_eq!(lz::decompress(&packed, &mut []), 0);
+    }
+
+    // random bytes only grow by a token per 127 literals and the end marker
+    #[test]
+    fn lz_incompressible() {
+        let mut noise = [0u8; 300];
+        let mut x: u16 = 0xACE1;
+        for byte in noise.iter_mut() {
+            x ^= x << 7;
+            x ^= x >> 9;
+            x ^= x << 8;
+            *byte = x as u8;
+        }
+        let packed = lz::compress(&noise);
+        assert!(packed.len() <= noise.len() + noise.len() / 127 + 2);
+        let mut output = [0u8; 300];
+        assert_eq!(lz::decompress(&packed, &mut output), noise.len());
+        assert!(output == noise);
+    }
+
+    #[test]
+    fn lz_long_run() {
+        let run = [7u8; 1000];
+        let packed = lz::compress(&run);
+        assert!(packed.len() < 32);
+        let mut output = [0u8; 1000];
+        assert_eq!(lz::decompress(&packed, &mut output), run.len());
+        assert!(output == run);
+    }
+
+    // one literal, then the rest as a single match of the longest length
+    #[test]
+    fn lz_max_match() {
+        let run = [0x55u8; 1 + lz::MAX_MATCH];
+        let packed = lz::compress(&run);
+        assert_eq!(&packed[..], &[1, 0x55, 0xFF, 1, 0, 0]);
+        let mut output = [0u8; 1 + lz::MAX_MATCH];
+        assert_eq!(lz::decompress(&packed, &mut output), run.len());
+        assert!(output == run);
+    }
+
+    // the standard check values of both CRCs
+    #[test]
+    fn crc_check_values() {
+        let mut crc16 = Crc16::new();
+        crc16.update(b"1234");
+        crc16.update(b"56789");
+        assert_eq!(crc16.finish(), 0x29B1);
+        let mut crc32 = Crc32::new();
+        crc32.update(b"123456789");
+        assert_eq!(crc32.finish(), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn seal_and_check() {
+        let chunk = reu().alloc(256);
+        chunk.write(0, b"SAVEGAME");
+        chunk.seal();
+        assert!(chunk.check());
+        chunk.write(3, &b'X');
+        assert!(!chunk.check());
+    }
+
+    // 10 runs of 4: odd run counts in the first passes, four passes end
+    // in the array chunk
+    #[test]
+    fn array_sort_odd_runs() {
+        super::check_sort(37, 4);
+    }
+
+    // 8 runs of 6: three passes end in the scratch chunk, which the array keeps
+    #[test]
+    fn array_sort_scratch() {
+        super::check_sort(48, 6);
+    }
+
+    // everything fits in the window, no merge pass at all
+    #[test]
+    fn array_sort_in_window() {
+        super::check_sort(20, 32);
+        super::check_sort(1, 3);
+    }
+
+    #[test]
+    fn array_binary_search() {
+        let mut array = REUArray::<u16>::with_capacity(500, 8);
+        for i in 0..500 {
+            array.push(i * 2);
+        }
+        for key in [0u16, 2, 498, 500, 998] {
+            assert_eq!(array.binary_search_by(|x| x.cmp(&key)), Ok(key as u32 / 2));
+        }
+        for key in [1u16, 499, 997] {
+            assert_eq!(
+                array.binary_search_by(|x| x.cmp(&key)),
+                Err(key as u32 / 2 + 1)
+            );
+        }
+        assert_eq!(array.binary_search_by(|x| x.cmp(&1000)), Err(500));
+        assert_eq!(
+            REUArray::<u16>::with_capacity(4, 4).binary_search_by(|x| x.cmp(&1)),
+            Err(0)
+        );
+    }
+
+    // writes at both ends of the window reach REU, the elements between
+    // them keep their values
+    #[test]
+    fn array_dirty_span() {
+        let mut array = REUArray::<u16>::with_capacity(100, 10);
+        for i in 0..100 {
+            array.push(i);
+        }
+        array.set_alignment(WindowAlignment::Aligned);
+        array[21] = 1000;
+        array[28] = 2000;
+        assert_eq!(array[95], 95);
+        let mut page = [0u16; 10];
+        array.copy_to_slice(20..30, &mut page);
+        assert_eq!(page, [20, 1000, 22, 23, 24, 25, 26, 27, 2000, 29]);
+        // a write right after a reload
+        array[50] = 5;
+        array.flush();
+        array.copy_to_slice(49..52, &mut page[..3]);
+        assert_eq!(&page[..3], &[49, 5, 51]);
+    }
+
+    // every alignment reads the same elements scanning up, down and jumping
+    #[test]
+    fn array_windowing() {
+        let mut array = REUArray::<u32>::with_capacity(300, 16);
+        for i in 0..300 {
+            array.push(i * 3);
+        }
+        for alignment in [
+            WindowAlignment::Adaptive,
+            WindowAlignment::Forward,
+            WindowAlignment::Backward,
+            WindowAlignment::Centered,
+            WindowAlignment::Aligned,
+        ] {
+            array.set_alignment(alignment);
+            for (i, x) in array.iter().enumerate() {
+                assert_eq!(x, i as u32 * 3);
+            }
+            for (value, index) in array.iter_range(5..295).rev().zip((5..295).rev()) {
+                assert_eq!(value, index * 3);
+            }
+            let mut noise = super::Noise(0xBEEF);
+            for _ in 0..100 {
+                let index = noise.next() as u32 % 300;
+                assert_eq!(array[index], index * 3);
+            }
+            assert_eq!(array[0], 0);
+            assert_eq!(array[299], 897);
+        }
+        array.for_each_mut(|x| *x += 1);
+        let mut expected = 299 * 3 + 1;
+        array.for_each_mut_rev(|x| {
+            assert_eq!(*x, expected);
+            expected = expected.wrapping_sub(3);
+            *x = 0;
+        });
+        assert!(array.iter().all(|x| x == 0));
+    }
+
+    #[test]
+    fn array_bulk_transfers() {
+        let mut array = REUArray::<u16>::with_capacity(1000, 8);
+        let items: [u16; 300] = core::array::from_fn(|i| i as u16);
+        array.extend_from_slice(&items);
+        array.extend_from_slice(&items);
+        assert_eq!(array.len(), 600);
+        assert_eq!(array[299], 299);
+        assert_eq!(array[300], 0);
+        // element 5 is modified in the window when the copy replaces it
+        assert_eq!(array[4], 4);
+        array[5] = 99;
+        array.copy_from_slice(0..10, &[7; 10]);
+        assert_eq!(array[5], 7);
+        assert_eq!(array[10], 10);
+        array.fill(100..550, 0xAAAA);
+        assert_eq!(array[99], 99);
+        assert!(array.iter_range(100..550).all(|x| x == 0xAAAA));
+        assert_eq!(array[550], 250);
+        array.resize(1000, 1);
+        assert_eq!(array[999], 1);
+        let mut out = [0u16; 4];
+        array.copy_to_slice(548..552, &mut out);
+        assert_eq!(out, [0xAAAA, 0xAAAA, 250, 251]);
+    }
+
+    // u32 keys and values give 25 keys per node, 1000 entries take three
+    // levels of split nodes and more than one REU segment
+    #[test]
+    fn btree_split() {
+        let mut map = REUBTreeMap::<u32, u32>::new();
+        for i in 0..1000u32 {
+            let key = i * 7919 % 1000;
+            assert_eq!(map.insert(key, key * 2), None);
+        }
+        assert_eq!(map.len(), 1000);
+        assert_eq!(map.insert(500, 1), Some(1000));
+        assert_eq!(map.insert(500, 1000), Some(1));
+        for key in 0..1000 {
+            assert_eq!(map.get(&key), Some(key * 2));
+        }
+        assert_eq!(map.get(&1000), None);
+        for (i, (key, value)) in map.iter().enumerate() {
+            assert_eq!(key, i as u32);
+            assert_eq!(value, key * 2);
+        }
+        assert_eq!(map.first_key_value(), Some((0, 0)));
+        assert_eq!(map.last_key_value(), Some((999, 1998)));
+    }
+
+    // removing refills nodes from siblings and merges them, down to an
+    // empty root
+    #[test]
+    fn btree_merge_on_remove() {
+        let mut map = REUBTreeMap::<u32, u32>::new();
+        for key in 0..1000 {
+            map.insert(key, key);
+        }
+        for i in 0..500u32 {
+            let key = i * 7919 % 500 * 2;
+            assert_eq!(map.remove(&key), Some(key));
+            assert_eq!(map.remove(&key), None);
+        }
+        assert_eq!(map.len(), 500);
+        assert!(map
+            .iter()
+            .map(|(key, _)| key)
+            .eq((0..500).map(|i| i * 2 + 1)));
+        for key in (0..1000).rev().filter(|key| key % 2 == 1) {
+            assert_eq!(map.remove(&key), Some(key));
+        }
+        assert!(map.is_empty());
+        assert_eq!(map.iter().next(), None);
+        assert_eq!(map.first_key_value(), None);
+        // freed nodes are used again
+        for key in 0..1000 {
+            map.insert(key, key + 1);
+        }
+        assert_eq!(map.get(&999), Some(1000));
+        assert_eq!(map.len(), 1000);
+    }
+
+    #[test]
+    fn btree_range() {
+        let mut map = REUBTreeMap::<u32, u32>::new();
+        for key in 0..500 {
+            map.insert(key * 2, key);
+        }
+        assert!(map
+            .range(100..200)
+            .map(|(key, _)| key)
+            .eq((50..100).map(|i| i * 2)));
+        assert!(map
+            .range(101..=200)
+            .map(|(key, _)| key)
+            .eq((51..=100).map(|i| i * 2)));
+        assert!(map.range(..=10).map(|(_, value)| value).eq(0..=5));
+        assert!(map
+            .range(990..)
+            .map(|(key, _)| key)
+            .eq([990, 992, 994, 996, 998]));
+        assert!(map
+            .range((Bound::Excluded(4), Bound::Excluded(10)))
+            .map(|(key, _)| key)
+            .eq([6, 8]));
+        assert_eq!(map.range(500..500).next(), None);
+        assert_eq!(map.range(1000..).next(), None);
+        assert_eq!(map.range(..0).next(), None);
+    }
+
+    // 256-byte objects store their length as 255, 17 of them take two slabs
+    #[test]
+    fn slab_largest_class() {
+        let mut slab = ReuSlab::new();
+        let mut handles = [slab.alloc(256); 17];
+        for (i, handle) in handles.iter_mut().enumerate().skip(1) {
+            *handle = slab.alloc(if i % 2 == 0 { 256 } else { 129 });
+        }
+        for (i, handle) in handles.iter().enumerate() {
+            let len = slab.len(*handle);
+            assert_eq!(len, if i % 2 == 0 { 256 } else { 129 });
+            let data: [u8; 256] = core::array::from_fn(|j| (i + j) as u8);
+            slab.write(*handle, &data[..len as usize]);
+        }
+        for (i, handle) in handles.iter().enumerate() {
+            let mut buffer = [0u8; 300];
+            let len = slab.read(*handle, &mut buffer);
+            assert_eq!(len, slab.len(*handle) as usize);
+            assert!(buffer[..len]
+                .iter()
+                .enumerate()
+                .all(|(j, &x)| x == (i + j) as u8));
+        }
+        // freed objects come back from the free list, last freed first
+        slab.free(handles[3]);
+        slab.free(handles[16]);
+        let again = slab.alloc(200);
+        assert_eq!(again.address(), handles[16].address());
+        assert_eq!(slab.len(again), 200);
+        assert_eq!(slab.alloc(256).address(), handles[3].address());
+        // a short object is a class of its own
+        let short = slab.alloc(1);
+        slab.write(short, b"X");
+        let mut buffer = [0u8; 4];
+        assert_eq!(slab.read(short, &mut buffer), 1);
+        assert_eq!(buffer[0], b'X');
+    }
+
+    // blocks longer than the copy buffer slide down over holes smaller than
+    // themselves, so source and destination overlap
+    #[test]
+    fn heap_compact() {
+        let mut heap = ReuHeap::with_capacity(4096);
+        let lens = [10u16, 300, 20, 500, 7, 260];
+        let handles = lens.map(|len| heap.alloc(len));
+        for (i, (handle, len)) in handles.iter().zip(lens).enumerate() {
+            let data: [u8; 500] = core::array::from_fn(|j| (i * 31 + j) as u8);
+            heap.write(*handle, 0, &data[..len as usize]);
+        }
+        let free_space = heap.free_space();
+        heap.free(handles[0]);
+        heap.free(handles[2]);
+        heap.free(handles[4]);
+        assert_eq!(heap.free_space(), free_space + 10 + 20 + 7 + 3 * 4);
+        let start = heap.address(handles[1]) - 4 - 10 - 4;
+        heap.compact();
+        assert_eq!(heap.free_space(), free_space + 10 + 20 + 7 + 3 * 4);
+        assert_eq!(heap.address(handles[1]), start + 4);
+        assert_eq!(heap.address(handles[3]), start + 4 + 300 + 4);
+        assert_eq!(heap.address(handles[5]), start + 4 + 300 + 4 + 500 + 4);
+        for i in [1, 3, 5] {
+            let mut buffer = [0u8; 500];
+            let len = heap.len(handles[i]) as usize;
+            heap.read(handles[i], 0, &mut buffer[..len]);
+            assert!(buffer[..len]
+                .iter()
+                .enumerate()
+                .all(|(j, &x)| x == (i * 31 + j) as u8));
+        }
+    }
+
+    // alloc compacts by itself when only the holes together have room
+    #[test]
+    fn heap_compact_on_alloc() {
+        let mut heap = ReuHeap::with_capacity(1024);
+        let blocks = [0u8; 4].map(|_| heap.alloc(250));
+        for (i, block) in blocks.iter().enumerate() {
+            heap.write(*block, 249, &[i as u8]);
+        }
+        heap.free(blocks[0]);
+        heap.free(blocks[2]);
+        let big = heap.alloc(500);
+        heap.write(big, 0, &[0xBB; 500]);
+        let mut byte = [0u8];
+        heap.read(blocks[1], 249, &mut byte);
+        assert_eq!(byte[0], 1);
+        heap.read(blocks[3], 249, &mut byte);
+        assert_eq!(byte[0], 3);
+        heap.read(big, 499, &mut byte);
+        assert_eq!(byte[0], 0xBB);
+        assert_eq!(heap.free_space(), 1024 - 2 * 254 - 504);
+    }
+
+    // writes grow the file through several chunks, reads and seeks see
+    // the same bytes, deleted files refuse their old handles
+    #[test]
+    fn ram_disk_files() {
+        let mut disk = RamDisk::new();
+        assert!(matches!(disk.create(b""), Err(FsError::BadName)));
+        assert!(matches!(
+            disk.create(b"SEVENTEEN CHARSXX"),
+            Err(FsError::BadName)
+        ));
+        let mut file = disk.create(b"LEVEL1").ok().unwrap();
+        assert!(matches!(disk.create(b"LEVEL1"), Err(FsError::Exists)));
+
+        for block in 0..10u8 {
+            let data: [u8; 100] = core::array::from_fn(|i| block ^ i as u8);
+            assert!(disk.write(&mut file, &data).is_ok());
+        }
+        assert!(disk.file_len(&file) == Ok(1000));
+        assert_eq!(file.position(), 1000);
+        let mut buffer = [0u8; 10];
+        assert!(disk.read(&mut file, &mut buffer) == Ok(0));
+
+        // overwrite in the middle, the length stays
+        assert!(disk.seek(&mut file, 10).is_ok());
+        assert!(disk.write(&mut file, b"ABC").is_ok());
+        assert!(disk.file_len(&file) == Ok(1000));
+        assert!(disk.seek(&mut file, 1001) == Err(FsError::BadPosition));
+
+        let mut other = disk.open(b"LEVEL1").ok().unwrap();
+        let mut contents = [0u8; 1000];
+        assert!(disk.read(&mut other, &mut contents[..600]) == Ok(600));
+        assert!(disk.read(&mut other, &mut contents[600..]) == Ok(400));
+        for (i, &byte) in contents.iter().enumerate() {
+            let expected = match i {
+                10 => b'A',
+                11 => b'B',
+                12 => b'C',
+                _ => (i / 100) as u8 ^ (i % 100) as u8,
+            };
+            assert_eq!(byte, expected);
+        }
+        assert!(disk.seek(&mut other, 995).is_ok());
+        assert!(disk.read(&mut other, &mut buffer) == Ok(5));
+
+        let mut empty = disk.create(b"EMPTY").ok().unwrap();
+        assert!(disk.read(&mut empty, &mut buffer) == Ok(0));
+        assert_eq!(disk.list().count(), 2);
+
+        assert!(disk.delete(b"LEVEL1").is_ok());
+        assert!(!disk.exists(b"LEVEL1"));
+        assert!(disk.delete(b"LEVEL1") == Err(FsError::NotFound));
+        assert!(matches!(disk.open(b"LEVEL1"), Err(FsError::NotFound)));
+        assert!(disk.read(&mut other, &mut buffer) == Err(FsError::BadHandle));
+        // a new file in the freed slot doesn't revive the old handles
+        let mut new = disk.create(b"LEVEL2").ok().unwrap();
+        assert!(disk.write(&mut file, b"X") == Err(FsError::BadHandle));
+        assert!(disk.write(&mut new, b"X").is_ok());
+        assert!(disk
+            .list()
+            .map(|(name, len)| (name == b"LEVEL2", len))
+            .any(|f| f == (true, 1)));
+    }
 }
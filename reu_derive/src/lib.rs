@@ -0,0 +1,54 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, parse_quote, Data, DeriveInput, GenericParam};
+
+/// `#[derive(ReuSafe)]` for the `reu` crate
+///
+/// Implements `reu::ReuSafe` for a struct and checks at compile time that
+/// every field is `ReuSafe` as well. Enums aren't accepted, as not every
+/// byte value is a valid discriminant.
+#[proc_macro_derive(ReuSafe)]
+pub fn derive_reu_safe(input: TokenStream) -> TokenStream {
+    let mut input = parse_macro_input!(input as DeriveInput);
+
+    let field_types: Vec<syn::Type> = match &input.data {
+        Data::Struct(data) => data.fields.iter().map(|f| f.ty.clone()).collect(),
+        Data::Enum(_) => {
+            return syn::Error::new_spanned(
+                &input.ident,
+                "ReuSafe can't be derived for enums, store the discriminant as an integer",
+            )
+            .to_compile_error()
+            .into();
+        }
+        Data::Union(_) => {
+            return syn::Error::new_spanned(&input.ident, "ReuSafe can't be derived for unions")
+                .to_compile_error()
+                .into();
+        }
+    };
+
+    // same as derive(Copy): every type parameter has to be ReuSafe too
+    for param in &mut input.generics.params {
+        if let GenericParam::Type(param) = param {
+            param.bounds.push(parse_quote!(::reu::ReuSafe));
+        }
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let expanded = quote! {
+        unsafe impl #impl_generics ::reu::ReuSafe for #name #ty_generics #where_clause {}
+
+        const _: () = {
+            fn check<T: ::reu::ReuSafe>() {}
+            #[allow(dead_code)]
+            fn check_fields #impl_generics () #where_clause {
+                #( check::<#field_types>(); )*
+            }
+        };
+    };
+
+    expanded.into()
+}